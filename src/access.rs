@@ -0,0 +1,208 @@
+//! A record of every request, separate from the application log so it can be kept,
+//! rotated and fed to log analyzers on its own.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write as _},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local};
+use eyre::Context;
+use log::error;
+use serde::{Deserialize, Serialize};
+use tiny_http::Request;
+
+use crate::{cache::Lookup, http::find_header};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// The Combined Log Format, with the latency and cache lookup added to the end.
+    #[default]
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub format:    Format,
+    /// Where to write the log. Standard error is used if this isn't set.
+    pub path:      Option<PathBuf>,
+    /// How big the log file can get before it's rotated.
+    pub max_bytes: u64,
+    /// How many rotated log files to keep around, as `<path>.1`, `<path>.2` and so on.
+    pub keep:      usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            format:    Format::default(),
+            path:      None,
+            max_bytes: 10 * 1024 * 1024,
+            keep:      5,
+        }
+    }
+}
+
+/// What happened with a request.
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub remote:     Option<String>,
+    pub time:       DateTime<Local>,
+    pub method:     String,
+    pub path:       String,
+    pub version:    String,
+    pub status:     u16,
+    pub bytes:      Option<usize>,
+    #[serde(rename = "latency_ms", serialize_with = "millis")]
+    pub latency:    Duration,
+    pub referrer:   Option<String>,
+    pub user_agent: Option<String>,
+    pub cache:      Option<Lookup>,
+}
+
+fn millis<S: serde::Serializer>(latency: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(latency.as_micros() as f64 / 1000.0)
+}
+
+impl Entry {
+    /// Everything but the latency, which isn't known until the response has been sent.
+    pub fn new(
+        request: &Request,
+        status: u16,
+        bytes: Option<usize>,
+        cache: Option<Lookup>,
+    ) -> Self {
+        let header = |field| find_header(request.headers(), field).map(str::to_string);
+        Self {
+            remote: request.remote_addr().map(|addr| addr.ip().to_string()),
+            time: SystemTime::now().into(),
+            method: request.method().to_string(),
+            path: request.url().to_string(),
+            version: request.http_version().to_string(),
+            status,
+            bytes,
+            latency: Duration::ZERO,
+            referrer: header("Referer"),
+            user_agent: header("User-Agent"),
+            cache,
+        }
+    }
+
+    fn combined(&self) -> String {
+        // Quotes would end the field early, and nothing else in it is escaped
+        let quoted = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        let or_dash = |value: Option<&str>| quoted(value.unwrap_or("-"));
+        format!(
+            r#"{} - - [{}] "{} {} HTTP/{}" {} {} "{}" "{}" {:.3}ms {}"#,
+            self.remote.as_deref().unwrap_or("-"),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            quoted(&self.path),
+            self.version,
+            self.status,
+            self.bytes
+                .map_or("-".to_string(), |bytes| bytes.to_string()),
+            or_dash(self.referrer.as_deref()),
+            or_dash(self.user_agent.as_deref()),
+            self.latency.as_secs_f64() * 1000.0,
+            self.cache.map_or("-", Lookup::name),
+        )
+    }
+}
+
+enum Sink {
+    Stderr,
+    File { file: File, written: u64 },
+}
+
+pub struct AccessLog {
+    config: Config,
+    sink:   Mutex<Sink>,
+}
+
+impl AccessLog {
+    pub fn new(config: Config) -> eyre::Result<Self> {
+        let sink = match &config.path {
+            Some(path) => {
+                let file = open(path).context(format!("Failed to open {path:?}"))?;
+                let written = file.metadata()?.len();
+                Sink::File { file, written }
+            }
+            None => Sink::Stderr,
+        };
+        Ok(Self {
+            config,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    pub fn record(&self, entry: &Entry) {
+        let mut line = match self.config.format {
+            Format::Combined => entry.combined(),
+            Format::Json => match serde_json::to_string(entry) {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("Failed to serialize access log entry: {e}");
+                    return;
+                }
+            },
+        };
+        line.push('\n');
+
+        let mut sink = self.sink.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = match &mut *sink {
+            Sink::Stderr => io::stderr().write_all(line.as_bytes()),
+            Sink::File { file, written } => {
+                *written += line.len() as u64;
+                file.write_all(line.as_bytes())
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to write to the access log: {e}");
+        }
+        let full =
+            matches!(&*sink, Sink::File { written, .. } if *written >= self.config.max_bytes);
+        if full && let Err(e) = self.rotate(&mut sink) {
+            error!("Failed to rotate the access log: {e}");
+        }
+    }
+
+    /// Shift each old log along by one, dropping the oldest, and start a new one.
+    fn rotate(&self, sink: &mut Sink) -> io::Result<()> {
+        let Some(path) = &self.config.path else {
+            return Ok(());
+        };
+        let rotated = |n: usize| {
+            let mut name = path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        if self.config.keep == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..self.config.keep).rev() {
+                if let Err(e) = fs::rename(rotated(n), rotated(n + 1))
+                    && e.kind() != io::ErrorKind::NotFound
+                {
+                    return Err(e);
+                }
+            }
+            fs::rename(path, rotated(1))?;
+        }
+        *sink = Sink::File {
+            file:    open(path)?,
+            written: 0,
+        };
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,141 @@
+//! The sockets the site is served on.
+use std::{
+    fmt, fs, io,
+    net::TcpListener,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use serde::Deserialize;
+
+/// One place to listen, as written in `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Bind {
+    /// An address and port like `0.0.0.0:8000` or `[::]:8000`, or the path to a Unix
+    /// socket if it starts with `/`.
+    Address(String),
+    /// A Unix socket, with permissions written in octal like `mode = 0o660` so a proxy
+    /// in another group can connect to it.
+    Unix { path: PathBuf, mode: Option<u32> },
+}
+
+/// `bind` can be a single place to listen or a list of them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Binds {
+    One(Bind),
+    Many(Vec<Bind>),
+}
+
+impl Default for Binds {
+    fn default() -> Self {
+        Self::One(Bind::Address("0.0.0.0:8000".to_string()))
+    }
+}
+
+impl Binds {
+    pub fn into_vec(self) -> Vec<Bind> {
+        match self {
+            Self::One(bind) => vec![bind],
+            Self::Many(binds) => binds,
+        }
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address(address) => write!(f, "{address}"),
+            Self::Unix { path, .. } => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Bind {
+    pub fn listen(&self) -> eyre::Result<Socket> {
+        let socket = match self {
+            Self::Address(path) if path.starts_with('/') => listen_unix(Path::new(path), None),
+            Self::Address(address) => TcpListener::bind(address).map(Socket::Tcp),
+            Self::Unix { path, mode } => listen_unix(path, *mode),
+        };
+        socket.context(format!("Failed to listen on {self}"))
+    }
+}
+
+fn listen_unix(path: &Path, mode: Option<u32>) -> io::Result<Socket> {
+    // A socket left behind by a server that didn't get to clean up would stop this
+    // one from starting, but one that's still being served mustn't be taken over
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "something other than a socket is there",
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is listening on it",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(Socket::Unix(listener))
+}
+
+/// A socket that's being listened on.
+pub enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Socket {
+    /// A handle for tiny_http, which closes the one it's given when it's done.
+    pub fn try_clone(&self) -> io::Result<tiny_http::Listener> {
+        Ok(match self {
+            Self::Tcp(listener) => listener.try_clone()?.into(),
+            Self::Unix(listener) => listener.try_clone()?.into(),
+        })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().ok().map(|addr| addr.port()),
+            Self::Unix(_) => None,
+        }
+    }
+
+    pub fn is_tcp(&self) -> bool {
+        matches!(self, Self::Tcp(_))
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = || "an unknown address".to_string();
+        match self {
+            Self::Tcp(listener) => {
+                let addr = listener.local_addr().map(|addr| addr.to_string());
+                write!(f, "{}", addr.unwrap_or_else(|_| unknown()))
+            }
+            Self::Unix(listener) => {
+                let addr = listener.local_addr().ok();
+                let path = addr.as_ref().and_then(|addr| addr.as_pathname());
+                write!(
+                    f,
+                    "{}",
+                    path.map_or_else(unknown, |path| path.display().to_string())
+                )
+            }
+        }
+    }
+}
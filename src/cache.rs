@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use ahash::RandomState;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compress::{self, Encoded},
+    http::{Body, CachePolicy, Reply, etag, find_header, header},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// How many pages to keep at most.
+    pub max_entries: usize,
+    /// How many bytes of pages to keep at most.
+    pub max_bytes:   usize,
+    /// How many seconds a page is kept before it's rendered again, even if nothing it
+    /// depends on seems to have changed.
+    pub ttl:         u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_entries: 256,
+            max_bytes:   16 * 1024 * 1024,
+            ttl:         3600,
+        }
+    }
+}
+
+/// What a cached page was rendered from, so it can be thrown out when that changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Music,
+    Words,
+}
+
+/// Whether a page came from the cache, for the access log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lookup {
+    Hit,
+    Miss,
+}
+
+impl Lookup {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub hits:      u64,
+    pub misses:    u64,
+    pub evictions: u64,
+    pub entries:   usize,
+    pub bytes:     usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses, {} evictions, {} entries ({} bytes)",
+            self.hits, self.misses, self.evictions, self.entries, self.bytes
+        )
+    }
+}
+
+/// A successful reply, kept so it doesn't have to be rendered or compressed again.
+struct Page {
+    status:        u16,
+    headers:       Vec<tiny_http::Header>,
+    body:          Arc<Encoded>,
+    cache:         CachePolicy,
+    last_modified: Option<SystemTime>,
+    expires:       Option<SystemTime>,
+}
+
+impl Page {
+    fn reply(&self) -> Reply {
+        Reply {
+            status:        self.status,
+            headers:       self.headers.clone(),
+            body:          Body::Shared(Arc::clone(&self.body)),
+            cache:         self.cache,
+            last_modified: self.last_modified,
+            expires:       self.expires,
+            lookup:        Some(Lookup::Hit),
+        }
+    }
+}
+
+struct Entry {
+    page:      Page,
+    source:    Source,
+    inserted:  Instant,
+    last_used: u64,
+}
+
+/// Rendered pages, keyed by a normalized form of the request that only includes the
+/// query parameters the page actually looks at. The least recently used pages are
+/// dropped to stay under the configured limits.
+pub struct RenderCache {
+    config:     Config,
+    entries:    HashMap<String, Entry, RandomState>,
+    /// Incremented on every lookup, to order entries by how recently they were used.
+    clock:      u64,
+    /// Incremented on every invalidation, so a page that was being rendered while its
+    /// source changed isn't kept.
+    generation: u64,
+    stats:      Stats,
+}
+
+impl RenderCache {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            entries: HashMap::default(),
+            clock: 0,
+            generation: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// The cached page for `key`, unless it isn't cached, has outlived the TTL or has
+    /// changed by itself since it was rendered. On a miss, this also returns the
+    /// generation to pass to [`RenderCache::keep`].
+    fn get(&mut self, key: &str) -> Result<Reply, u64> {
+        self.clock += 1;
+        let ttl = Duration::from_secs(self.config.ttl);
+        if let Some(entry) = self.entries.get_mut(key) {
+            let changed = entry
+                .page
+                .expires
+                .is_some_and(|expires| expires <= SystemTime::now());
+            if entry.inserted.elapsed() < ttl && !changed {
+                entry.last_used = self.clock;
+                self.stats.hits += 1;
+                return Ok(entry.page.reply());
+            }
+            self.remove(key);
+        }
+        self.stats.misses += 1;
+        Err(self.generation)
+    }
+
+    /// Keep a freshly rendered reply if it's successful and nothing was invalidated
+    /// since `generation`, and hand back one that can be sent.
+    fn keep(&mut self, key: String, source: Source, mut reply: Reply, generation: u64) -> Reply {
+        reply.lookup = Some(Lookup::Miss);
+        if reply.status != 200 {
+            return reply;
+        }
+        let body = match reply.body {
+            Body::Bytes(bytes) => {
+                let compressible = find_header(&reply.headers, "Content-Type")
+                    .is_some_and(compress::is_compressible);
+                Arc::new(Encoded::new(bytes, compressible))
+            }
+            Body::Shared(encoded) => encoded,
+            // Streamed bodies are never worth keeping in memory
+            body @ Body::Reader(..) => {
+                reply.body = body;
+                return reply;
+            }
+        };
+        // Work this out once rather than on every hit
+        if find_header(&reply.headers, "ETag").is_none() {
+            reply.headers.push(header("ETag", &etag(body.identity())));
+        }
+        reply.body = Body::Shared(Arc::clone(&body));
+
+        if generation == self.generation {
+            let page = Page {
+                status: reply.status,
+                headers: reply.headers.clone(),
+                body,
+                cache: reply.cache,
+                last_modified: reply.last_modified,
+                expires: reply.expires,
+            };
+            self.insert(key, source, page);
+        }
+        reply
+    }
+
+    fn insert(&mut self, key: String, source: Source, page: Page) {
+        let size = page.body.size();
+        if size > self.config.max_bytes || self.config.max_entries == 0 {
+            return;
+        }
+        while self.entries.len() >= self.config.max_entries
+            || self.stats.bytes + size > self.config.max_bytes
+        {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+
+        self.stats.bytes += size;
+        self.entries.insert(key, Entry {
+            page,
+            source,
+            inserted: Instant::now(),
+            last_used: self.clock,
+        });
+        self.stats.entries = self.entries.len();
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.stats.bytes -= entry.page.body.size();
+            self.stats.entries = self.entries.len();
+        }
+    }
+
+    /// Forget every page rendered from `source`.
+    pub fn invalidate(&mut self, source: Source) {
+        self.generation += 1;
+        let stale: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.source == source)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            self.remove(&key);
+        }
+    }
+}
+
+/// Lock a cache shared between workers. A worker that panicked can't have left it half
+/// updated, so there's no reason to give up on it.
+pub fn lock(cache: &Mutex<RenderCache>) -> std::sync::MutexGuard<'_, RenderCache> {
+    cache.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Get the page for `key`, rendering it if it isn't cached or has expired. Only
+/// successful replies are kept. The cache isn't locked while rendering, so other
+/// workers can carry on in the meantime.
+pub fn get_or_render(
+    cache: &Mutex<RenderCache>,
+    key: String,
+    source: Source,
+    render: impl FnOnce() -> Reply,
+) -> Reply {
+    let generation = match lock(cache).get(&key) {
+        Ok(reply) => return reply,
+        Err(generation) => generation,
+    };
+    let reply = render();
+    lock(cache).keep(key, source, reply, generation)
+}
+
+/// Notices when the files that pages are rendered from change on disk, e.g. when a
+/// post is edited or a cron job refreshes the music cache.
+pub struct Watcher {
+    sources:    Vec<(Source, Vec<PathBuf>, Option<SystemTime>)>,
+    interval:   Duration,
+    last_check: Instant,
+}
+
+impl Watcher {
+    /// Watch each source's files and directories. For a directory, the newest of it and
+    /// its entries counts, so files being added, removed or edited are all noticed.
+    pub fn new(sources: Vec<(Source, Vec<PathBuf>)>) -> Self {
+        let sources = sources
+            .into_iter()
+            .map(|(source, paths)| {
+                let stamp = stamp(&paths);
+                (source, paths, stamp)
+            })
+            .collect();
+        Self {
+            sources,
+            interval: Duration::from_secs(1),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// The sources that have changed since the last call. This only looks at the
+    /// filesystem once per `interval`.
+    pub fn poll(&mut self) -> Vec<Source> {
+        if self.last_check.elapsed() < self.interval {
+            return vec![];
+        }
+        self.last_check = Instant::now();
+
+        let mut changed = vec![];
+        for (source, paths, last_stamp) in &mut self.sources {
+            let stamp = stamp(paths);
+            if stamp != *last_stamp {
+                *last_stamp = stamp;
+                changed.push(*source);
+            }
+        }
+        changed
+    }
+}
+
+fn stamp(paths: &[PathBuf]) -> Option<SystemTime> {
+    fn modified(path: &Path) -> Option<SystemTime> {
+        path.metadata().and_then(|m| m.modified()).ok()
+    }
+
+    paths
+        .iter()
+        .flat_map(|path| {
+            let children = path
+                .read_dir()
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| modified(&entry.ok()?.path()));
+            modified(path).into_iter().chain(children)
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{Config, Lookup, RenderCache, Source, get_or_render};
+    use crate::http::Reply;
+
+    fn cache(max_entries: usize, max_bytes: usize, ttl: u64) -> Mutex<RenderCache> {
+        Mutex::new(RenderCache::new(Config {
+            max_entries,
+            max_bytes,
+            ttl,
+        }))
+    }
+
+    /// Look up `key`, rendering a 10 byte page on a miss. Returns whether it was a hit.
+    fn hit(cache: &Mutex<RenderCache>, key: &str, source: Source) -> bool {
+        let reply = get_or_render(cache, key.to_string(), source, || Reply::html("0123456789"));
+        reply.lookup == Some(Lookup::Hit)
+    }
+
+    #[test]
+    fn keeps_pages() {
+        let cache = cache(10, 1000, 60);
+        assert!(!hit(&cache, "/a", Source::Words));
+        assert!(hit(&cache, "/a", Source::Words));
+        let stats = cache.lock().unwrap().stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+        assert_eq!(stats.bytes, 10);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = cache(2, 1000, 60);
+        hit(&cache, "/a", Source::Words);
+        hit(&cache, "/b", Source::Words);
+        assert!(hit(&cache, "/a", Source::Words));
+        hit(&cache, "/c", Source::Words);
+        assert_eq!(cache.lock().unwrap().stats().evictions, 1);
+        assert!(hit(&cache, "/a", Source::Words));
+        assert!(hit(&cache, "/c", Source::Words));
+        assert!(!hit(&cache, "/b", Source::Words));
+    }
+
+    #[test]
+    fn stays_under_max_bytes() {
+        let cache = cache(10, 25, 60);
+        hit(&cache, "/a", Source::Words);
+        hit(&cache, "/b", Source::Words);
+        hit(&cache, "/c", Source::Words);
+        let stats = cache.lock().unwrap().stats();
+        assert_eq!((stats.entries, stats.bytes, stats.evictions), (2, 20, 1));
+        assert!(!hit(&cache, "/a", Source::Words));
+
+        // Pages bigger than the whole cache aren't kept at all
+        let tiny = self::cache(10, 5, 60);
+        hit(&tiny, "/a", Source::Words);
+        assert!(!hit(&tiny, "/a", Source::Words));
+    }
+
+    #[test]
+    fn invalidates_by_source() {
+        let cache = cache(10, 1000, 60);
+        hit(&cache, "/words", Source::Words);
+        hit(&cache, "/music", Source::Music);
+        cache.lock().unwrap().invalidate(Source::Words);
+        assert!(!hit(&cache, "/words", Source::Words));
+        assert!(hit(&cache, "/music", Source::Music));
+    }
+
+    #[test]
+    fn drops_pages_rendered_during_invalidation() {
+        let cache = cache(10, 1000, 60);
+        get_or_render(&cache, "/a".to_string(), Source::Words, || {
+            // The files change while the page is being rendered from the old ones
+            cache.lock().unwrap().invalidate(Source::Words);
+            Reply::html("0123456789")
+        });
+        assert!(!hit(&cache, "/a", Source::Words));
+        assert!(hit(&cache, "/a", Source::Words));
+    }
+
+    #[test]
+    fn expires() {
+        let cache = cache(10, 1000, 0);
+        hit(&cache, "/a", Source::Words);
+        assert!(!hit(&cache, "/a", Source::Words));
+
+        // A page that changes by itself, like when a scheduled post is published
+        let cache = self::cache(10, 1000, 60);
+        let render = || {
+            Reply::html("0123456789").with_expires(Some(SystemTime::now() - Duration::from_secs(1)))
+        };
+        get_or_render(&cache, "/a".to_string(), Source::Words, render);
+        let reply = get_or_render(&cache, "/a".to_string(), Source::Words, render);
+        assert_eq!(reply.lookup, Some(Lookup::Miss));
+    }
+
+    #[test]
+    fn only_keeps_successes() {
+        let cache = cache(10, 1000, 60);
+        let render = || Reply::new(404, "text/html", "Not found");
+        get_or_render(&cache, "/a".to_string(), Source::Words, render);
+        let reply = get_or_render(&cache, "/a".to_string(), Source::Words, render);
+        assert_eq!(reply.lookup, Some(Lookup::Miss));
+    }
+}
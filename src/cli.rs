@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use eyre::{bail, eyre};
+
+use crate::{NAME, page::music::Refresh};
+
+const COMMANDS: &str = "\
+Commands:
+  serve                              Serve the site (default)
+  refresh [--force] [--only <RGID>]  Rebuild the music cache and exit
+  check                              Validate the configuration and Words frontmatter
+  export <DIR>                       Render the site into DIR as static files, which
+                                     needs public_url to be set
+  help                               Print this message
+";
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Serve,
+    Refresh(Refresh),
+    Check,
+    Export { out_dir: PathBuf },
+    Help,
+}
+
+pub fn print_usage() {
+    println!("Usage: {NAME} [COMMAND]\n\n{COMMANDS}");
+}
+
+/// Parse the command-line arguments, not including the program name.
+pub fn parse(mut args: impl Iterator<Item = String>) -> eyre::Result<Command> {
+    let Some(command) = args.next() else {
+        return Ok(Command::Serve);
+    };
+
+    let command = match command.as_str() {
+        "serve" => Command::Serve,
+        "refresh" => {
+            let mut refresh = Refresh::default();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-f" | "--force" => refresh.force = true,
+                    "--only" => {
+                        let rgid = args
+                            .next()
+                            .ok_or_else(|| eyre!("`--only` expects a release group MBID"))?;
+                        refresh.only = Some(rgid);
+                    }
+                    _ => bail!("Unexpected argument to `refresh`: {arg:?}"),
+                }
+            }
+            Command::Refresh(refresh)
+        }
+        "check" => Command::Check,
+        "export" => {
+            let out_dir = args
+                .next()
+                .ok_or_else(|| eyre!("`export` expects an output directory"))?;
+            Command::Export {
+                out_dir: out_dir.into(),
+            }
+        }
+        "-h" | "--help" | "help" => Command::Help,
+        _ => bail!("Unknown command: {command:?} (try `{NAME} help`)"),
+    };
+
+    if let Some(arg) = args.next() {
+        bail!("Unexpected argument: {arg:?}");
+    }
+    Ok(command)
+}
@@ -0,0 +1,248 @@
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use flate2::{Compression, write::GzEncoder};
+
+/// Bodies smaller than this aren't worth compressing.
+const MIN_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// The name used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+        }
+    }
+}
+
+/// How hard to try when compressing. Only brotli gets much slower for doing better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effort {
+    /// For a body compressed again on every request, which anyone can ask for.
+    Fast,
+    /// For copies that are kept and served many times.
+    Best,
+}
+
+/// Whether responses of this `Content-Type` are text that compresses well.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/xml"
+                | "application/atom+xml"
+                | "application/rss+xml"
+                | "application/javascript"
+                | "image/svg+xml"
+        )
+}
+
+/// Pick the best encoding the client accepts, preferring brotli when it's just as
+/// welcome as gzip.
+pub fn negotiate(accept_encoding: Option<&str>) -> Encoding {
+    let Some(accept_encoding) = accept_encoding else {
+        return Encoding::Identity;
+    };
+    let brotli = quality(accept_encoding, Encoding::Brotli.name());
+    let gzip = quality(accept_encoding, Encoding::Gzip.name());
+    if brotli > 0.0 && brotli >= gzip {
+        Encoding::Brotli
+    } else if gzip > 0.0 {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+/// The `q` value given to `coding` in an `Accept-Encoding` header.
+fn quality(accept_encoding: &str, coding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse().ok())
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = q;
+        }
+    }
+    wildcard
+}
+
+/// Compress `bytes`, or `None` if that wouldn't make them any smaller.
+pub fn compress(encoding: Encoding, effort: Effort, bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.len() < MIN_SIZE {
+        return None;
+    }
+    let compressed = match encoding {
+        Encoding::Identity => return None,
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).and_then(|()| encoder.finish())
+        }
+        Encoding::Brotli => {
+            let quality = match effort {
+                Effort::Fast => 4,
+                Effort::Best => 9,
+            };
+            let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+            writer.write_all(bytes).map(|()| writer.into_inner())
+        }
+    };
+    compressed
+        .ok()
+        .filter(|compressed| compressed.len() < bytes.len())
+}
+
+/// A body along with compressed copies of it, made once so they can be served any
+/// number of times.
+pub struct Encoded {
+    identity: Vec<u8>,
+    gzip:     Option<Vec<u8>>,
+    brotli:   Option<Vec<u8>>,
+}
+
+impl Encoded {
+    pub fn new(identity: Vec<u8>, compressible: bool) -> Self {
+        let (gzip, brotli) = if compressible {
+            (
+                compress(Encoding::Gzip, Effort::Best, &identity),
+                compress(Encoding::Brotli, Effort::Best, &identity),
+            )
+        } else {
+            (None, None)
+        };
+        Self {
+            identity,
+            gzip,
+            brotli,
+        }
+    }
+
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// How much memory all of the copies take up.
+    pub fn size(&self) -> usize {
+        self.identity.len()
+            + self.gzip.as_ref().map_or(0, Vec::len)
+            + self.brotli.as_ref().map_or(0, Vec::len)
+    }
+
+    /// The copy in `encoding` if there is one, otherwise the uncompressed one.
+    pub fn get(&self, encoding: Encoding) -> (Encoding, &[u8]) {
+        let compressed = match encoding {
+            Encoding::Identity => None,
+            Encoding::Gzip => self.gzip.as_deref(),
+            Encoding::Brotli => self.brotli.as_deref(),
+        };
+        match compressed {
+            Some(bytes) => (encoding, bytes),
+            None => (Encoding::Identity, &self.identity),
+        }
+    }
+}
+
+/// Reads one of the copies in an [`Encoded`] without having to clone it.
+pub struct EncodedReader {
+    encoded:  Arc<Encoded>,
+    encoding: Encoding,
+    position: usize,
+}
+
+impl EncodedReader {
+    pub fn new(encoded: Arc<Encoded>, encoding: Encoding) -> Self {
+        Self {
+            encoded,
+            encoding,
+            position: 0,
+        }
+    }
+}
+
+impl Read for EncodedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (_, bytes) = self.encoded.get(self.encoding);
+        let n = (&bytes[self.position..]).read(buf)?;
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{Effort, Encoding, compress, negotiate, quality};
+
+    #[test]
+    fn prefers_brotli() {
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Encoding::Brotli);
+        assert_eq!(negotiate(Some("br;q=0.5, gzip;q=0.5")), Encoding::Brotli);
+        assert_eq!(negotiate(Some("br;q=0.4, gzip;q=0.5")), Encoding::Gzip);
+    }
+
+    #[test]
+    fn q_zero_refuses() {
+        assert_eq!(negotiate(Some("br;q=0, gzip")), Encoding::Gzip);
+        assert_eq!(negotiate(Some("br;q=0, gzip;q=0")), Encoding::Identity);
+        assert_eq!(negotiate(Some("*;q=0")), Encoding::Identity);
+        assert_eq!(negotiate(Some("*, br;q=0")), Encoding::Gzip);
+    }
+
+    #[test]
+    fn nothing_accepted() {
+        assert_eq!(negotiate(None), Encoding::Identity);
+        assert_eq!(negotiate(Some("")), Encoding::Identity);
+        assert_eq!(negotiate(Some("identity")), Encoding::Identity);
+    }
+
+    #[test]
+    fn qualities() {
+        assert_eq!(quality("GZIP", "gzip"), 1.0);
+        assert_eq!(quality("gzip ; q=0.3", "gzip"), 0.3);
+        assert_eq!(quality("gzip;q=nope", "gzip"), 1.0);
+        assert_eq!(quality("*;q=0.2", "br"), 0.2);
+        assert_eq!(quality("deflate", "br"), 0.0);
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = "Hello, hello, hello. ".repeat(100);
+        for effort in [Effort::Fast, Effort::Best] {
+            let mut brotli = String::new();
+            let compressed = compress(Encoding::Brotli, effort, text.as_bytes()).unwrap();
+            brotli::Decompressor::new(compressed.as_slice(), 4096)
+                .read_to_string(&mut brotli)
+                .unwrap();
+            assert_eq!(brotli, text);
+
+            let mut gzip = String::new();
+            let compressed = compress(Encoding::Gzip, effort, text.as_bytes()).unwrap();
+            flate2::read::GzDecoder::new(compressed.as_slice())
+                .read_to_string(&mut gzip)
+                .unwrap();
+            assert_eq!(gzip, text);
+        }
+        assert_eq!(compress(Encoding::Brotli, Effort::Fast, b"too short"), None);
+    }
+}
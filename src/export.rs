@@ -0,0 +1,131 @@
+use std::{fs, path::Path};
+
+use eyre::Context;
+use log::info;
+use percent_encoding::percent_decode_str;
+
+use crate::page::{assets, error, escape, links, music, style::STYLESHEET, words};
+
+/// Render every page into `out_dir` so it can be put on plain static hosting. Links
+/// are written as directories (`/words/hello/`), each holding an `index.html`. The
+/// feeds' links start with `origin`, since feed readers need them to be absolute.
+pub fn run(out_dir: &Path, origin: &str) -> eyre::Result<()> {
+    links::set_style(links::Style::Static);
+
+    let music_index = links::music(None);
+    write(out_dir, "/", redirect_page(&music_index))?;
+    write(out_dir, "/404.html", error::not_found_page(""))?;
+
+    let releases = music::cached()?;
+    write(out_dir, &music_index, music::render(&releases, None))?;
+    for (sort, _label) in music::SORTS {
+        write(
+            out_dir,
+            &links::music(Some(sort)),
+            music::render(&releases, Some(sort)),
+        )?;
+    }
+
+    let content_dir = words::content_dir();
+    write(
+        out_dir,
+        &links::words_index(),
+        words::render_index(&content_dir)?,
+    )?;
+    write(
+        out_dir,
+        &links::words_feed(),
+        words::render_feed(&content_dir, Some(origin))?,
+    )?;
+    // Drafts and scheduled documents are left out, since a static site can't hide them
+    // or publish them later
+    let now = words::now();
+    let documents = words::documents(&content_dir)?;
+    for document in documents
+        .iter()
+        .filter(|document| document.meta.is_published(now))
+    {
+        write(
+            out_dir,
+            &links::words_document(&document.slug),
+            words::render_document(document)?,
+        )?;
+    }
+    for tag in words::tags(&words::listed(&content_dir)?) {
+        if let Some(html) = words::render_tag(&content_dir, &tag.slug)? {
+            write(out_dir, &links::words_tag(&tag.slug), html)?;
+        }
+        if let Some(xml) = words::render_tag_feed(&content_dir, &tag.slug, Some(origin))? {
+            write(out_dir, &links::words_tag_feed(&tag.slug), xml)?;
+        }
+    }
+
+    let assets_dir = assets::assets_dir();
+    if assets_dir.is_dir() {
+        copy_dir(
+            &assets_dir,
+            &out_dir.join(assets::PATH_PREFIX.trim_start_matches('/')),
+        )?;
+    }
+
+    write(
+        out_dir,
+        &links::stylesheet(),
+        STYLESHEET.contents.identity(),
+    )?;
+    Ok(())
+}
+
+/// Copy everything but hidden files from `from` into `to`, the same things the asset
+/// route would serve.
+fn copy_dir(from: &Path, to: &Path) -> eyre::Result<()> {
+    fs::create_dir_all(to).context(format!("Failed to create directory {to:?}"))?;
+    for entry in fs::read_dir(from).context(format!("Failed to read directory {from:?}"))? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let (from, to) = (entry.path(), to.join(entry.file_name()));
+        if from.is_dir() {
+            copy_dir(&from, &to)?;
+        } else if from.is_file() {
+            fs::copy(&from, &to).context(format!("Failed to copy {from:?} to {to:?}"))?;
+            info!("Wrote {to:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Static hosts can't send redirects, so `/` gets a page that does it instead.
+fn redirect_page(to: &str) -> String {
+    let to = escape(to);
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en-US">
+<head>
+<meta charset="utf-8" />
+<meta http-equiv="refresh" content="0; url={to}" />
+<link rel="canonical" href="{to}" />
+</head>
+<body><a href="{to}">{to}</a></body>
+</html>
+"#
+    )
+}
+
+/// Write `contents` to wherever `link` points inside of `out_dir`, which is what gets
+/// served from the base path.
+fn write(out_dir: &Path, link: &str, contents: impl AsRef<[u8]>) -> eyre::Result<()> {
+    // Static hosts look for files named after the decoded path
+    let link = percent_decode_str(links::strip_base_path(link)).decode_utf8_lossy();
+    let mut file = out_dir.join(link.trim_start_matches('/'));
+    if link.ends_with('/') {
+        file.push("index.html");
+    }
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).context(format!("Failed to create directory {parent:?}"))?;
+    }
+    fs::write(&file, contents).context(format!("Failed to write {file:?}"))?;
+    info!("Wrote {file:?}");
+    Ok(())
+}
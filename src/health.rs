@@ -0,0 +1,108 @@
+//! Probes for load balancers and orchestrators, so traffic only goes where it can be
+//! served.
+use std::{collections::BTreeMap, fs, path::Path};
+
+use log::error;
+use serde::Serialize;
+
+use crate::{
+    http::{CachePolicy, Reply},
+    page::{music, words},
+    shutdown,
+};
+
+#[derive(Debug, Serialize)]
+struct Check {
+    ok:     bool,
+    detail: String,
+}
+
+impl Check {
+    fn new(ok: bool, detail: impl Into<String>) -> Self {
+        Self {
+            ok,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready:  bool,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+/// The process is up and handling requests. Nothing else is checked, so a slow
+/// dependency can't get it restarted.
+pub fn liveness() -> Reply {
+    Reply::new(200, "text/plain; charset=utf-8", "ok\n").with_cache(CachePolicy::NoStore)
+}
+
+/// Whether everything pages are rendered from is available, with the state of each
+/// part as JSON. Answers 503 if anything isn't. `releases` is missing while they're
+/// still being loaded.
+pub fn readiness(releases: Option<usize>) -> Reply {
+    let checks = BTreeMap::from([
+        ("music", music_check(releases)),
+        ("words", words_check(&words::content_dir())),
+        ("music_cache", cache_check(&music::cache_path())),
+        ("shutdown", shutdown_check()),
+    ]);
+    let readiness = Readiness {
+        ready: checks.values().all(|check| check.ok),
+        checks,
+    };
+    let status = if readiness.ready { 200 } else { 503 };
+    let json = serde_json::to_string(&readiness).unwrap_or_else(|e| {
+        error!("Failed to serialize readiness: {e}");
+        String::from(r#"{"ready":false}"#)
+    });
+    Reply::new(status, "application/json", json).with_cache(CachePolicy::NoStore)
+}
+
+/// Requests are still handled while shutting down, but new traffic should go elsewhere.
+fn shutdown_check() -> Check {
+    if shutdown::requested() {
+        Check::new(false, "shutting down")
+    } else {
+        Check::new(true, "not shutting down")
+    }
+}
+
+/// Having no releases is fine if that's what's recommended, so only loading counts.
+fn music_check(releases: Option<usize>) -> Check {
+    match releases {
+        Some(releases) => Check::new(true, format!("{releases} releases loaded")),
+        None => Check::new(false, "loading"),
+    }
+}
+
+fn words_check(content_dir: &Path) -> Check {
+    match words::find_content(content_dir) {
+        Ok(content) => Check::new(true, format!("{} documents", content.len())),
+        Err(e) => Check::new(false, format!("can't read {content_dir:?}: {e}")),
+    }
+}
+
+/// The cache has to be writable for releases to be refreshed. Opening it to append
+/// checks that without changing anything.
+fn cache_check(path: &Path) -> Check {
+    if path.exists() {
+        return match fs::OpenOptions::new().append(true).open(path) {
+            Ok(_) => Check::new(true, "writable"),
+            Err(e) => Check::new(false, format!("can't write to {path:?}: {e}")),
+        };
+    }
+    let Some(parent) = path.parent() else {
+        return Check::new(false, format!("{path:?} has no parent directory"));
+    };
+    match fs::metadata(parent) {
+        Ok(metadata) if !metadata.permissions().readonly() => {
+            Check::new(true, "not created yet, but its directory is writable")
+        }
+        Ok(_) => Check::new(false, format!("{parent:?} is read-only")),
+        // The directory is created along with the cache
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Check::new(true, "not created yet"),
+        Err(e) => Check::new(false, format!("can't read {parent:?}: {e}")),
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    io::Read,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Utc};
+use tiny_http::{Header, Request, Response, ResponseBox, StatusCode};
+
+use crate::{
+    cache::Lookup,
+    compress::{self, Effort, Encoded, EncodedReader, Encoding},
+    page::fingerprint,
+};
+
+pub fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+/// Find the value of the request header named `field`.
+pub fn find_header<'a>(headers: &'a [Header], field: &'static str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|header| header.field.equiv(field))
+        .map(|header| header.value.as_str())
+}
+
+/// What the client asked a proxy for, from the proxy's `field` header, if it's trusted.
+fn forwarded<'a>(request: &'a Request, field: &'static str, trust_proxy: bool) -> Option<&'a str> {
+    // Each proxy along the way adds to the list, and the first is the client's
+    find_header(request.headers(), field)
+        .filter(|_| trust_proxy)
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+}
+
+/// Where the client reached the site, like `https://example.com`, as told by the
+/// request itself. A proxy's `X-Forwarded-Proto` and `X-Forwarded-Host` are only
+/// believed if `trust_proxy`, since otherwise any client could send them.
+pub fn request_origin(request: &Request, trust_proxy: bool) -> Option<String> {
+    let host = forwarded(request, "X-Forwarded-Host", trust_proxy)
+        .or_else(|| find_header(request.headers(), "Host"))
+        .filter(|host| {
+            !host.is_empty()
+                && host
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-.:[]".contains(&b))
+        })?;
+    let scheme = request_scheme(request, trust_proxy);
+    Some(format!("{scheme}://{}", host.to_ascii_lowercase()))
+}
+
+/// Whether the client made the request with `https` or `http`, as told by the request
+/// itself, or by a proxy's `X-Forwarded-Proto` if `trust_proxy`.
+pub fn request_scheme(request: &Request, trust_proxy: bool) -> &'static str {
+    match forwarded(request, "X-Forwarded-Proto", trust_proxy) {
+        Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+        Some(proto) if proto.eq_ignore_ascii_case("http") => "http",
+        _ if request.secure() => "https",
+        _ => "http",
+    }
+}
+
+/// Format a time the way HTTP headers like `Last-Modified` expect.
+pub fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// A strong `ETag` for a response body.
+pub fn etag(body: &[u8]) -> String {
+    format!(r#""{:016x}""#, fingerprint(body))
+}
+
+/// Each encoding of a body is a different representation, so needs its own `ETag`.
+fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match (encoding, etag.strip_suffix('"')) {
+        (Encoding::Identity, _) | (_, None) => etag.to_string(),
+        (encoding, Some(etag)) => format!(r#"{etag}-{}""#, encoding.name()),
+    }
+}
+
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    DateTime::parse_from_rfc2822(date.trim())
+        .ok()
+        .map(SystemTime::from)
+}
+
+/// How long clients and proxies may reuse a response without asking again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Don't keep it at all, e.g. for errors.
+    NoStore,
+    /// Reuse it for this many seconds, then revalidate.
+    Public(u32),
+    /// Reuse it forever. Only for URLs whose contents can never change.
+    Immutable,
+}
+
+impl CachePolicy {
+    /// Pages are cheap to revalidate, but there's no reason to make everyone do it on
+    /// every view.
+    pub const PAGE: Self = Self::Public(900);
+
+    /// Don't let it be reused past `expires`, if it's going to change then.
+    fn until(self, expires: Option<SystemTime>) -> Self {
+        match (self, expires) {
+            (Self::Public(max_age), Some(expires)) => {
+                let left = expires
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+                    .as_secs();
+                Self::Public(max_age.min(u32::try_from(left).unwrap_or(u32::MAX)))
+            }
+            _ => self,
+        }
+    }
+
+    fn header(self) -> Header {
+        match self {
+            Self::NoStore => header("Cache-Control", "no-store"),
+            Self::Public(max_age) => header("Cache-Control", &format!("public, max-age={max_age}")),
+            Self::Immutable => header("Cache-Control", "public, max-age=31536000, immutable"),
+        }
+    }
+}
+
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Bytes that are also kept elsewhere, like in the render cache, along with any
+    /// compressed copies of them.
+    Shared(Arc<Encoded>),
+    /// Something to stream, along with how many bytes it'll produce.
+    Reader(Box<dyn Read + Send>, usize),
+}
+
+/// Everything a route has to say about a response. Caching headers are worked out from
+/// this when it's turned into a [`ResponseBox`].
+pub struct Reply {
+    pub status:        u16,
+    pub headers:       Vec<Header>,
+    pub body:          Body,
+    pub cache:         CachePolicy,
+    pub last_modified: Option<SystemTime>,
+    /// When this will change by itself, e.g. because a scheduled document is published,
+    /// so it isn't reused past then.
+    pub expires:       Option<SystemTime>,
+    /// Whether this came from the render cache, if it could have.
+    pub lookup:        Option<Lookup>,
+}
+
+impl Reply {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![header("Content-Type", content_type)],
+            body: Body::Bytes(body.into()),
+            cache: CachePolicy::PAGE,
+            last_modified: None,
+            expires: None,
+            lookup: None,
+        }
+    }
+
+    pub fn html(html: impl Into<Vec<u8>>) -> Self {
+        Self::new(200, "text/html; charset=utf-8", html)
+    }
+
+    pub fn empty(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: Body::Bytes(vec![]),
+            cache: CachePolicy::NoStore,
+            last_modified: None,
+            expires: None,
+            lookup: None,
+        }
+    }
+
+    pub fn redirect(status: u16, location: &str) -> Self {
+        Self::empty(status).with_header(header("Location", location))
+    }
+
+    pub fn with_header(mut self, header: Header) -> Self {
+        self.headers.push(header);
+        self
+    }
+
+    pub fn with_body(mut self, body: Body) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: CachePolicy) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_last_modified(mut self, time: Option<SystemTime>) -> Self {
+        self.last_modified = time;
+        self
+    }
+
+    pub fn with_expires(mut self, time: Option<SystemTime>) -> Self {
+        self.expires = time;
+        self
+    }
+
+    fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Use the route's own `ETag` if it has one, otherwise derive a strong one from the
+    /// body.
+    fn etag(&self) -> Option<String> {
+        if let Some(etag) = find_header(&self.headers, "ETag") {
+            return Some(etag.to_string());
+        }
+        match &self.body {
+            Body::Bytes(bytes) if self.is_success() => Some(etag(bytes)),
+            Body::Shared(encoded) if self.is_success() => Some(etag(encoded.identity())),
+            _ => None,
+        }
+    }
+
+    /// Whether the copy the client already has is still good, per `If-None-Match` or,
+    /// failing that, `If-Modified-Since`.
+    fn is_fresh(&self, request_headers: &[Header], etag: Option<&str>) -> bool {
+        if let Some(if_none_match) = find_header(request_headers, "If-None-Match") {
+            let Some(etag) = etag else {
+                return false;
+            };
+            let etag = etag.trim_start_matches("W/");
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag);
+        }
+
+        match (
+            self.last_modified,
+            find_header(request_headers, "If-Modified-Since").and_then(parse_http_date),
+        ) {
+            // HTTP dates only have second precision
+            (Some(modified), Some(since)) => {
+                let secs =
+                    |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                secs(modified) <= secs(since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Text is compressed when the client accepts it, but anything streamed is sent
+    /// as is.
+    fn is_compressible(&self) -> bool {
+        !matches!(self.body, Body::Reader(..))
+            && find_header(&self.headers, "Content-Type").is_some_and(compress::is_compressible)
+    }
+
+    pub fn into_response(mut self, request_headers: &[Header]) -> ResponseBox {
+        let compressible = self.is_compressible();
+        let accepted = if compressible {
+            compress::negotiate(find_header(request_headers, "Accept-Encoding"))
+        } else {
+            Encoding::Identity
+        };
+        let etag = self.etag();
+        let success = self.is_success();
+        let body = std::mem::replace(&mut self.body, Body::Bytes(vec![]));
+        let (reader, len, encoding): (Box<dyn Read + Send>, _, _) = match body {
+            Body::Bytes(bytes) => match compress::compress(accepted, Effort::Fast, &bytes) {
+                Some(compressed) => {
+                    let len = compressed.len();
+                    (Box::new(std::io::Cursor::new(compressed)), len, accepted)
+                }
+                None => {
+                    let len = bytes.len();
+                    (
+                        Box::new(std::io::Cursor::new(bytes)),
+                        len,
+                        Encoding::Identity,
+                    )
+                }
+            },
+            Body::Shared(encoded) => {
+                let (encoding, bytes) = encoded.get(accepted);
+                let len = bytes.len();
+                (
+                    Box::new(EncodedReader::new(encoded, encoding)),
+                    len,
+                    encoding,
+                )
+            }
+            Body::Reader(reader, len) => (reader, len, Encoding::Identity),
+        };
+        let etag = etag.map(|etag| encoded_etag(&etag, encoding));
+
+        let mut validators = vec![];
+        if success {
+            if let Some(etag) = etag.as_deref() {
+                validators.push(header("ETag", etag));
+            }
+            if let Some(modified) = self.last_modified {
+                validators.push(header("Last-Modified", &http_date(modified)));
+            }
+        }
+        validators.push(self.cache.until(self.expires).header());
+        if compressible {
+            validators.push(header("Vary", "Accept-Encoding"));
+        }
+        self.headers
+            .retain(|existing| !validators.iter().any(|v| v.field == existing.field));
+
+        if success && self.is_fresh(request_headers, etag.as_deref()) {
+            return Response::new(
+                StatusCode(304),
+                validators,
+                Box::new(std::io::empty()) as Box<dyn Read + Send>,
+                Some(0),
+                None,
+            );
+        }
+
+        self.headers.extend(validators);
+        if encoding != Encoding::Identity {
+            self.headers
+                .push(header("Content-Encoding", encoding.name()));
+        }
+        Response::new(
+            StatusCode(self.status),
+            self.headers,
+            reader,
+            Some(len),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use tiny_http::{Header, Request, TestRequest};
+
+    use super::{Reply, header, http_date, request_origin};
+
+    fn page() -> Reply {
+        Reply::html("<p>Hello</p>".repeat(100))
+    }
+
+    /// The status and `ETag` of the response to a request with `headers`.
+    fn respond(reply: Reply, headers: &[Header]) -> (u16, Option<String>) {
+        let response = reply.into_response(headers);
+        let etag = response
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("ETag"))
+            .map(|header| header.value.to_string());
+        (response.status_code().0, etag)
+    }
+
+    fn etag(accept_encoding: &str) -> String {
+        respond(page(), &[header("Accept-Encoding", accept_encoding)])
+            .1
+            .unwrap()
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = etag("identity");
+        let status =
+            |if_none_match: &str| respond(page(), &[header("If-None-Match", if_none_match)]).0;
+        assert_eq!(status(&etag), 304);
+        assert_eq!(status(&format!("W/{etag}")), 304);
+        assert_eq!(status(&format!(r#""other", {etag}"#)), 304);
+        assert_eq!(status("*"), 304);
+        assert_eq!(status(r#""other""#), 200);
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let status = |since: SystemTime| {
+            let reply = page().with_last_modified(Some(modified));
+            respond(reply, &[header("If-Modified-Since", &http_date(since))]).0
+        };
+        // The header can't say anything about the half second
+        assert_eq!(status(modified), 304);
+        assert_eq!(status(modified + Duration::from_secs(60)), 304);
+        assert_eq!(status(modified - Duration::from_secs(1)), 200);
+
+        // Without a time to compare against, there's no telling
+        let headers = [header("If-Modified-Since", &http_date(modified))];
+        assert_eq!(respond(page(), &headers).0, 200);
+    }
+
+    #[test]
+    fn if_none_match_wins() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let reply = page().with_last_modified(Some(modified));
+        let headers = [
+            header("If-None-Match", r#""other""#),
+            header("If-Modified-Since", &http_date(modified)),
+        ];
+        assert_eq!(respond(reply, &headers).0, 200);
+    }
+
+    #[test]
+    fn encoded_etags() {
+        let identity = etag("identity");
+        let brotli = etag("br");
+        let gzip = etag("gzip");
+        assert_eq!(brotli, format!("{}-br\"", identity.trim_end_matches('"')));
+        assert_eq!(gzip, format!("{}-gzip\"", identity.trim_end_matches('"')));
+
+        let status = |accept_encoding: &str, if_none_match: &str| {
+            let headers = [
+                header("Accept-Encoding", accept_encoding),
+                header("If-None-Match", if_none_match),
+            ];
+            respond(page(), &headers).0
+        };
+        assert_eq!(status("br", &brotli), 304);
+        assert_eq!(status("gzip", &gzip), 304);
+        // A copy in another encoding isn't the same representation
+        assert_eq!(status("br", &gzip), 200);
+        assert_eq!(status("br", &identity), 200);
+        assert_eq!(status("identity", &brotli), 200);
+    }
+
+    #[test]
+    fn errors_are_never_fresh() {
+        let reply = Reply::new(404, "text/html; charset=utf-8", "Not found");
+        assert_eq!(respond(reply, &[header("If-None-Match", "*")]).0, 404);
+    }
+
+    #[test]
+    fn forwarded_origins() {
+        let request = || {
+            TestRequest::new()
+                .with_header(header("Host", "Internal:8000"))
+                .with_header(header("X-Forwarded-Host", "example.com, proxy.local"))
+                .with_header(header("X-Forwarded-Proto", "https"))
+        };
+        let origin = |request: TestRequest, trust_proxy| {
+            request_origin(&Request::from(request), trust_proxy)
+        };
+        assert_eq!(
+            origin(request(), true).as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            origin(request(), false).as_deref(),
+            Some("http://internal:8000")
+        );
+        assert_eq!(
+            origin(request().with_https(), false).as_deref(),
+            Some("https://internal:8000")
+        );
+    }
+}
@@ -1,12 +1,41 @@
 #![feature(result_option_map_or_default)]
-use ahash::RandomState;
+use access::AccessLog;
+use cache::{RenderCache, Source, Watcher, get_or_render};
 use eyre::Context;
+use http::{CachePolicy, Reply};
+use log::{debug, error, info, warn};
+use page::links;
+use percent_encoding::percent_decode_str;
 use serde::Deserialize;
-use std::{collections::HashMap, fmt::Debug, fs, io::Read, path::Path};
+use std::{
+    fmt::Debug,
+    fs,
+    io::Read,
+    num::NonZero,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, PoisonError, RwLock, RwLockReadGuard,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 use tiny_http::{Header, Method, Request, Response};
-use uri_rs::UriOwned;
+use uri_rs::{QueryParameters, UriOwned};
 
+mod access;
+mod bind;
+mod cache;
+mod cli;
+mod compress;
+mod export;
+mod health;
+mod http;
+mod metrics;
 mod page;
+mod security;
+mod shutdown;
+mod tls;
 #[macro_use]
 mod macros;
 
@@ -15,7 +44,45 @@ pub const NAME: &str = env!("CARGO_CRATE_NAME");
 
 #[derive(Debug, Deserialize)]
 struct Config {
-    bind: Option<String>,
+    /// Where to serve the site. Defaults to `0.0.0.0:8000`.
+    #[serde(default)]
+    bind:             bind::Binds,
+    /// Serve `/metrics` from here instead of alongside the site, so it can be kept
+    /// off the public internet.
+    admin_bind:       Option<String>,
+    /// How many requests to handle at once. Defaults to the number of CPUs.
+    workers:          Option<NonZero<usize>>,
+    /// How many seconds to wait for requests that are still being handled when asked
+    /// to shut down.
+    shutdown_timeout: Option<u64>,
+    /// Serve HTTPS on the addresses in `bind`, but not Unix sockets, using this
+    /// certificate chain and private key, both PEM encoded. Sending SIGHUP loads them
+    /// again.
+    tls_cert:         Option<PathBuf>,
+    tls_key:          Option<PathBuf>,
+    /// Redirect plain HTTP requests made here to HTTPS.
+    redirect_bind:    Option<String>,
+    /// Where the site is mounted when a proxy serves it from somewhere other than the
+    /// root, e.g. `/recs`. Defaults to the path of `public_url`.
+    base_path:        Option<String>,
+    /// Where visitors reach the site, e.g. `https://example.com/recs`, for links that
+    /// have to be absolute. Without it, they're worked out from each request.
+    public_url:       Option<String>,
+    /// Believe the `X-Forwarded-Host` and `X-Forwarded-Proto` headers, which is only
+    /// safe if every request comes through a proxy that sets them.
+    #[serde(default)]
+    trusted_proxy:    bool,
+    /// Shows Words documents that aren't published yet to whoever adds it to the link,
+    /// as in `/words/hello?preview={token}`.
+    preview_token:    Option<String>,
+    #[serde(default)]
+    cache:            cache::Config,
+    #[serde(default)]
+    access_log:       access::Config,
+    #[serde(default)]
+    security_headers: security::Config,
+    #[serde(default)]
+    words:            page::words::Config,
 }
 
 fn load_config(path: impl AsRef<Path>) -> eyre::Result<Config> {
@@ -27,100 +94,684 @@ fn load_config(path: impl AsRef<Path>) -> eyre::Result<Config> {
     Ok(config)
 }
 
-fn main() -> eyre::Result<()> {
-    env_logger::builder().init();
-    let config_dir = dirs::config_dir()
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
         .expect("System should have a config directory")
-        .join(NAME);
-    let config_path = config_dir.join("config.toml");
-    let config = load_config(&config_path)?;
-    let bind = config.bind.unwrap_or_else(|| "0.0.0.0:8000".to_string());
-
-    let rstate = RandomState::default();
-    let mut cache: HashMap<u64, String, RandomState> = HashMap::default();
-    let music_releases = page::music::prepare()?;
-
-    let caching_headers: &[Header] = &[
-        "Content-Type: text/html".parse().unwrap(),
-        "Cache-Control: public, max-age=900".parse().unwrap(),
-    ];
-    let server = tiny_http::Server::http(bind).unwrap();
-    loop {
-        let mut request = match server.recv() {
-            Ok(rq) => rq,
-            Err(e) => {
-                eprintln!("error: {e}");
-                break;
+        .join(NAME)
+}
+
+fn main() -> eyre::Result<()> {
+    // Progress used to be printed unconditionally, so keep showing it by default
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    match cli::parse(std::env::args().skip(1))? {
+        cli::Command::Serve => {
+            shutdown::listen()?;
+            serve(load_config(config_dir().join("config.toml"))?)
+        }
+        cli::Command::Refresh(options) => {
+            shutdown::listen()?;
+            let releases = page::music::refresh(&options)?;
+            println!("Cached {} releases", releases.len());
+            Ok(())
+        }
+        cli::Command::Check => check(),
+        cli::Command::Export { out_dir } => {
+            let path = config_dir().join("config.toml");
+            let config = load_config(&path)?;
+            page::words::configure(config.words.clone());
+            // Feeds need absolute links, and nothing else says where the files will be
+            let origin = public_origin(&config)?.ok_or_else(|| {
+                eyre::eyre!("Exporting needs public_url to be set in {path:?}, for the feeds")
+            })?;
+            export::run(&out_dir, &origin)
+        }
+        cli::Command::Help => {
+            cli::print_usage();
+            Ok(())
+        }
+    }
+}
+
+/// Mount every link under the base path, and return the origin of `public_url` if it's
+/// set.
+fn public_origin(config: &Config) -> eyre::Result<Option<String>> {
+    let (origin, path) = match &config.public_url {
+        Some(url) => {
+            let (origin, path) = links::split_url(url).context("Invalid public_url")?;
+            (Some(origin.to_string()), path)
+        }
+        None => (None, ""),
+    };
+    links::set_base_path(config.base_path.as_deref().unwrap_or(path));
+    Ok(origin)
+}
+
+/// Validate everything that's loaded at startup without touching the network.
+fn check() -> eyre::Result<()> {
+    let mut ok = true;
+
+    match load_config(config_dir().join("config.toml")) {
+        Ok(config) => {
+            // Dates in documents are checked in the configured timezone
+            page::words::configure(config.words);
+            println!("config.toml: ok");
+        }
+        Err(e) => {
+            ok = false;
+            println!("config.toml: {e:#}");
+        }
+    }
+
+    match page::music::check() {
+        Ok(0) => println!("music.toml: ok"),
+        Ok(missing) => println!("music.toml: ok ({missing} releases aren't cached yet)"),
+        Err(e) => {
+            ok = false;
+            println!("music.toml: {e:#}");
+        }
+    }
+
+    match page::words::check(&page::words::content_dir()) {
+        Ok((n, problems)) if problems.is_empty() => println!("words: ok ({n} documents)"),
+        Ok((_, problems)) => {
+            ok = false;
+            for problem in problems {
+                println!("words: {problem}");
             }
+        }
+        Err(e) => {
+            ok = false;
+            println!("words: {e:#}");
+        }
+    }
+
+    if !ok {
+        eyre::bail!("Configuration is invalid");
+    }
+    Ok(())
+}
+
+/// The releases every music page is rendered from.
+struct Music {
+    releases: Vec<page::music::Release>,
+    /// Every page is rendered from the releases loaded here, so this is the last time
+    /// any of them could have changed. Missing until they're first loaded.
+    loaded:   Option<SystemTime>,
+}
+
+/// Everything the workers share.
+struct State {
+    music:            RwLock<Music>,
+    cache:            Mutex<RenderCache>,
+    watcher:          Mutex<Watcher>,
+    access_log:       AccessLog,
+    requests:         metrics::Requests,
+    /// Whether admin routes have their own listener, rather than sharing the public one.
+    admin_bind:       bool,
+    /// Sent with every response.
+    security_headers: Vec<Header>,
+    /// Only sent with responses over HTTPS.
+    hsts:             Option<Header>,
+    /// Where HTTPS is served, for redirecting plain HTTP requests there.
+    https_port:       Option<u16>,
+    /// From `public_url`, which is trusted over anything requests say.
+    public_origin:    Option<String>,
+    trusted_proxy:    bool,
+    preview_token:    Option<String>,
+}
+
+impl State {
+    fn music(&self) -> RwLockReadGuard<'_, Music> {
+        self.music.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Load the releases for the first time, fetching any that aren't cached yet.
+    fn load_music(&self) -> eyre::Result<()> {
+        let releases = page::music::prepare()?;
+        *self.music.write().unwrap_or_else(PoisonError::into_inner) = Music {
+            releases,
+            loaded: Some(SystemTime::now()),
         };
-        let method = request.method().to_owned();
-        let headers = request.headers();
-        let url = request.url();
-        let Ok(url) = UriOwned::new(url) else {
-            let _ = request.respond(Response::new_empty(tiny_http::StatusCode(404)));
-            continue;
-        };
-        let Some(mut path) = url.path.as_deref() else {
-            let _ = request.respond(Response::new_empty(tiny_http::StatusCode(404)));
-            continue;
+        Ok(())
+    }
+
+    /// Throw out anything rendered from files that have changed. Only one worker needs
+    /// to look, so the others skip this if it's already happening.
+    fn refresh(&self) {
+        let Ok(mut watcher) = self.watcher.try_lock() else {
+            return;
         };
-        if path.ends_with('/') && path != "/" {
-            path = &path[0..path.len() - 1];
-        }
-
-        let query = url.as_ref().get_query_parameters().unwrap_or_default();
-
-        let key = {
-            let mut key: Vec<u8> = vec![];
-            key.extend_from_slice(method.as_str().as_bytes());
-            // Whitelist headers to include
-            headers
-                .iter()
-                .filter(|_| false)
-                .map(|x| (x.field.as_str().as_bytes(), x.value.as_str().as_bytes()))
-                .for_each(|(k, v)| {
-                    key.extend_from_slice(k);
-                    key.extend_from_slice(v);
-                });
-            key.extend_from_slice(request.url().as_bytes());
-            let body = {
-                let mut body = vec![];
-                request.as_reader().read_to_end(&mut body)?;
-                body
-            };
-            key.extend_from_slice(&body);
-            rstate.hash_one(key)
+        for source in watcher.poll() {
+            // Releases are only reloaded once the first load is done, since it's the one
+            // that says when they're ready
+            if source == Source::Music && self.music().loaded.is_some() {
+                match page::music::cached() {
+                    Ok(releases) => {
+                        info!("Reloaded {} releases", releases.len());
+                        *self.music.write().unwrap_or_else(PoisonError::into_inner) = Music {
+                            releases,
+                            loaded: Some(SystemTime::now()),
+                        };
+                    }
+                    Err(e) => {
+                        error!("Failed to reload releases: {e}");
+                        continue;
+                    }
+                }
+            }
+            if source == Source::Words {
+                page::words::forget_documents();
+            }
+            let mut cache = cache::lock(&self.cache);
+            cache.invalidate(source);
+            debug!("{source:?} changed; render cache: {}", cache.stats());
+        }
+    }
+}
+
+fn serve(config: Config) -> eyre::Result<()> {
+    let public_origin = public_origin(&config)?;
+    page::words::configure(config.words.clone());
+    let workers = config
+        .workers
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(4, NonZero::get);
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout.unwrap_or(30));
+    let certificate = match (config.tls_cert, config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::Certificate { cert, key }),
+        (None, None) => None,
+        _ => eyre::bail!("tls_cert and tls_key have to be set together"),
+    };
+    if config.redirect_bind.is_some() && certificate.is_none() {
+        eyre::bail!("redirect_bind needs tls_cert and tls_key to be set");
+    }
+
+    let mut sockets = from_systemd()?;
+    let from_systemd = !sockets.is_empty();
+    if from_systemd {
+        info!("Using the sockets passed in by systemd");
+    } else {
+        for bind in config.bind.into_vec() {
+            sockets.push(bind.listen()?);
+        }
+    }
+    let servers = sockets
+        .into_iter()
+        .map(|socket| {
+            info!("Listening on {socket}");
+            // Unix sockets are for a proxy on the same machine, which has no use for TLS
+            let certificate = certificate.clone().filter(|_| socket.is_tcp());
+            tls::Server::new(socket, certificate)
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    if servers.is_empty() {
+        eyre::bail!("There's nowhere to listen, since bind is empty");
+    }
+
+    let state = State {
+        music: RwLock::new(Music {
+            releases: Vec::new(),
+            loaded:   None,
+        }),
+        cache: Mutex::new(RenderCache::new(config.cache)),
+        watcher: Mutex::new(Watcher::new(vec![
+            (Source::Music, vec![
+                page::music::config_path(),
+                page::music::cache_path(),
+            ]),
+            (Source::Words, vec![page::words::content_dir()]),
+        ])),
+        access_log: AccessLog::new(config.access_log)?,
+        requests: metrics::Requests::default(),
+        admin_bind: config.admin_bind.is_some(),
+        security_headers: config.security_headers.headers(),
+        hsts: config.security_headers.hsts(certificate.is_some()),
+        https_port: servers
+            .iter()
+            .find(|server| server.is_secure())
+            .and_then(tls::Server::port),
+        public_origin,
+        trusted_proxy: config.trusted_proxy,
+        preview_token: config.preview_token.filter(|token| !token.is_empty()),
+    };
+
+    let admin = config
+        .admin_bind
+        .map(|bind| tls::Server::bind(&bind, None))
+        .transpose()?;
+    let redirect = config
+        .redirect_bind
+        .map(|bind| tls::Server::bind(&bind, None))
+        .transpose()?;
+    info!("Handling requests with {workers} workers");
+    notify_systemd(&[sd_notify::NotifyState::Ready]);
+
+    let (sender, receiver) = mpsc::channel();
+    let receiver = Mutex::new(receiver);
+    let music = thread::scope(|scope| {
+        // Fetching releases can take a while, and probes should be answered meanwhile
+        let music = scope.spawn(|| {
+            let loaded = state.load_music();
+            if loaded.is_err() {
+                shutdown::request();
+            }
+            loaded
+        });
+        let listeners = servers
+            .iter()
+            .map(|server| (server, Listener::Public))
+            .chain(admin.iter().map(|server| (server, Listener::Admin)))
+            .chain(redirect.iter().map(|server| (server, Listener::Redirect)));
+        for (server, listener) in listeners {
+            let sender = sender.clone();
+            scope.spawn(move || accept(server, listener, &sender));
+        }
+        // The workers stop once every listener has and nothing's left to handle
+        drop(sender);
+        for _ in 0..workers {
+            scope.spawn(|| work(&receiver, &state));
+        }
+
+        shutdown::wait();
+        notify_systemd(&[sd_notify::NotifyState::Stopping]);
+        // Slow clients shouldn't be able to hold up a restart forever
+        thread::spawn(move || {
+            thread::sleep(shutdown_timeout);
+            error!("Requests were still being handled after {shutdown_timeout:?}; exiting");
+            std::process::exit(1);
+        });
+        music
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    });
+    info!("Finished handling requests");
+    if from_systemd {
+        // tiny_http removes Unix sockets when it's done with them, but these belong to
+        // systemd and need to be there when the next server starts
+        std::mem::forget(servers);
+    }
+
+    music
+}
+
+/// Sockets systemd opened for us, if it started us with socket activation.
+fn from_systemd() -> eyre::Result<Vec<bind::Socket>> {
+    let mut fds = listenfd::ListenFd::from_env();
+    let mut sockets = Vec::new();
+    for i in 0..fds.len() {
+        // Each of these leaves the socket alone if it's the wrong kind
+        let socket = match fds.take_tcp_listener(i) {
+            Ok(listener) => listener.map(bind::Socket::Tcp),
+            Err(_) => fds
+                .take_unix_listener(i)
+                .context(format!("Socket {i} isn't a TCP or Unix socket"))?
+                .map(bind::Socket::Unix),
         };
+        sockets.extend(socket);
+    }
+    Ok(sockets)
+}
 
-        let mut response = match (method, path) {
-            (Method::Get, "/") => Response::empty(308)
-                .with_header(Header::from_bytes(b"Location", page::music::PATH.as_bytes()).unwrap())
-                .boxed(),
-            (Method::Get, "/music") => {
-                let html = cache.get(&key).cloned().unwrap_or_else(|| {
-                    let v = page::music::render(&music_releases, &query);
-                    cache.insert(key, v.clone());
-                    v
-                });
-                Response::from_string(html).boxed()
-            }
-            (Method::Get, "/words") => page::words::render(&query),
-            _ => {
-                eprintln!("Couldn't find {path:?}");
-                Response::new_empty(tiny_http::StatusCode(404)).boxed()
+/// Pass requests from `server` on to the workers until asked to shut down. After that
+/// only requests that are already waiting are passed on, so a busy server still stops.
+fn accept(server: &tls::Server, listener: Listener, requests: &Sender<(Request, Listener)>) {
+    while !shutdown::requested() {
+        if let Err(e) = server.reload_if_requested() {
+            error!("Failed to restart the server after reloading its certificate: {e:#}");
+            shutdown::request();
+            return;
+        }
+        let request = match server.recv_timeout(Duration::from_millis(500)) {
+            Ok(Some(rq)) => rq,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Failed to receive a request: {e}");
+                shutdown::request();
+                return;
             }
         };
-        for header in caching_headers.iter() {
-            response.add_header(header.clone());
+        if requests.send((request, listener)).is_err() {
+            return;
+        }
+    }
+    while let Ok(Some(request)) = server.try_recv() {
+        if requests.send((request, listener)).is_err() {
+            return;
         }
-        respond_or_complain(request, response);
     }
+}
 
-    Ok(())
+/// Handle requests from every listener until they've all stopped.
+fn work(requests: &Mutex<Receiver<(Request, Listener)>>, state: &State) {
+    loop {
+        let received = requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .recv();
+        let Ok((request, listener)) = received else {
+            break;
+        };
+        state.refresh();
+        handle(state, request, listener);
+    }
+}
+
+/// Tell systemd how things are going, if it started us.
+fn notify_systemd(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        debug!("Failed to notify systemd: {e}");
+    }
+}
+
+/// The methods every route accepts. HEAD is answered like GET, and tiny_http leaves the
+/// body out.
+const ALLOW: &str = "GET, HEAD, OPTIONS";
+
+/// Everything that can be requested, worked out from the path alone so methods that
+/// aren't allowed can be told apart from paths that don't exist.
+#[derive(Debug, Clone, Copy)]
+enum Route {
+    Root,
+    Music,
+    Words,
+    WordsDocument,
+    WordsFeed,
+    WordsTag,
+    WordsTagFeed,
+    Asset,
+    Style,
+    Metrics,
+    Healthz,
+    Readyz,
+}
+
+/// The slug in a path like `/words/tags/{slug}`.
+fn tag_slug(path: &str) -> Option<&str> {
+    path.strip_prefix("/words/tags/")
+        .filter(|slug| !slug.is_empty() && !slug.contains('/'))
+}
+
+/// Links in feeds include the origin, so each one is cached separately.
+fn feed_origin(state: &State, request: &Request) -> Option<String> {
+    state
+        .public_origin
+        .clone()
+        .or_else(|| http::request_origin(request, state.trusted_proxy))
+}
+
+/// Which socket a request came in on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Listener {
+    Public,
+    Admin,
+    /// Plain HTTP, redirected to HTTPS.
+    Redirect,
+}
+
+impl Route {
+    fn find(path: &str) -> Option<Self> {
+        match path {
+            "/metrics" => Some(Self::Metrics),
+            "/healthz" => Some(Self::Healthz),
+            "/readyz" => Some(Self::Readyz),
+            "/" => Some(Self::Root),
+            "/music" => Some(Self::Music),
+            "/words" => Some(Self::Words),
+            "/words/feed.xml" => Some(Self::WordsFeed),
+            path if path.starts_with(page::assets::PATH_PREFIX) => Some(Self::Asset),
+            path if path.starts_with(page::style::PATH_PREFIX) && path.ends_with(".css") => {
+                Some(Self::Style)
+            }
+            path if tag_slug(path).is_some() => Some(Self::WordsTag),
+            path if path.strip_suffix("/feed.xml").and_then(tag_slug).is_some() => {
+                Some(Self::WordsTagFeed)
+            }
+            path if path
+                .strip_prefix("/words/")
+                .is_some_and(|slug| !slug.contains('/')) =>
+            {
+                Some(Self::WordsDocument)
+            }
+            _ => None,
+        }
+    }
+
+    /// The label used for this route in metrics.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Root => "root",
+            Self::Music => "music",
+            Self::Words => "words",
+            Self::WordsDocument => "words_document",
+            Self::WordsFeed => "words_feed",
+            Self::WordsTag => "words_tag",
+            Self::WordsTagFeed => "words_tag_feed",
+            Self::Asset => "asset",
+            Self::Style => "style",
+            Self::Metrics => "metrics",
+            Self::Healthz => "healthz",
+            Self::Readyz => "readyz",
+        }
+    }
+
+    /// Routes for whoever runs the site rather than its visitors.
+    fn is_admin(self) -> bool {
+        matches!(self, Self::Metrics)
+    }
+
+    /// Probes are answered everywhere, since load balancers check the port they send
+    /// traffic to.
+    fn is_probe(self) -> bool {
+        matches!(self, Self::Healthz | Self::Readyz)
+    }
+
+    /// Admin routes are only on the admin listener if there is one, and that's all it
+    /// serves besides probes.
+    fn is_served_on(self, listener: Listener, state: &State) -> bool {
+        match listener {
+            _ if self.is_probe() => true,
+            Listener::Admin => self.is_admin(),
+            Listener::Redirect => false,
+            Listener::Public => !self.is_admin() || !state.admin_bind,
+        }
+    }
+
+    fn get(self, state: &State, path: &str, query: &QueryParameters, request: &Request) -> Reply {
+        match self {
+            Self::Root => {
+                Reply::redirect(308, &links::music(None)).with_cache(CachePolicy::Public(86400))
+            }
+            Self::Music => {
+                let sort = page::music::sort_param(query);
+                let key = match sort {
+                    Some(sort) => format!("/music?sort={sort}"),
+                    None => "/music".to_string(),
+                };
+                get_or_render(&state.cache, key, Source::Music, || {
+                    let music = state.music();
+                    if music.loaded.is_none() {
+                        return Reply::new(503, "text/plain; charset=utf-8", "loading\n")
+                            .with_cache(CachePolicy::NoStore);
+                    }
+                    Reply::html(page::music::render(&music.releases, sort))
+                        .with_last_modified(music.loaded)
+                })
+            }
+            Self::Words => match page::words::title_param(query) {
+                Some(title) => {
+                    page::words::redirect(&percent_decode_str(title).decode_utf8_lossy())
+                }
+                None => get_or_render(
+                    &state.cache,
+                    path.to_string(),
+                    Source::Words,
+                    page::words::index,
+                ),
+            },
+            Self::WordsDocument => {
+                let slug = path.strip_prefix("/words/").unwrap_or_default();
+                let slug = percent_decode_str(slug).decode_utf8_lossy();
+                if let Some(location) = document_redirect(request.url(), &slug) {
+                    return Reply::redirect(308, &location).with_cache(CachePolicy::Public(86400));
+                }
+                // Previews are never cached, so they can't leak to anyone else
+                let preview = page::words::preview_param(query);
+                if preview.is_some() && preview == state.preview_token.as_deref() {
+                    return page::words::document(&slug, true);
+                }
+                get_or_render(&state.cache, path.to_string(), Source::Words, || {
+                    page::words::document(&slug, false)
+                })
+            }
+            Self::WordsFeed => {
+                let origin = feed_origin(state, request);
+                let key = format!("{}{path}", origin.as_deref().unwrap_or_default());
+                get_or_render(&state.cache, key, Source::Words, || {
+                    page::words::feed(origin.as_deref())
+                })
+            }
+            Self::WordsTag => {
+                let slug =
+                    percent_decode_str(tag_slug(path).unwrap_or_default()).decode_utf8_lossy();
+                get_or_render(&state.cache, path.to_string(), Source::Words, || {
+                    page::words::tag(&slug)
+                })
+            }
+            Self::WordsTagFeed => {
+                let slug = path.strip_suffix("/feed.xml").and_then(tag_slug);
+                let slug = percent_decode_str(slug.unwrap_or_default()).decode_utf8_lossy();
+                let origin = feed_origin(state, request);
+                let key = format!("{}{path}", origin.as_deref().unwrap_or_default());
+                get_or_render(&state.cache, key, Source::Words, || {
+                    page::words::tag_feed(&slug, origin.as_deref())
+                })
+            }
+            Self::Asset => page::assets::render(path, request.headers()),
+            Self::Style => page::style::render(path),
+            Self::Metrics => {
+                let metrics = metrics::Snapshot {
+                    requests:  &state.requests,
+                    cache:     cache::lock(&state.cache).stats(),
+                    releases:  state.music().releases.len(),
+                    documents: page::words::find_content(&page::words::content_dir())
+                        .map_or(0, |content| content.len()),
+                };
+                Reply::new(
+                    200,
+                    "text/plain; version=0.0.4; charset=utf-8",
+                    metrics.render(),
+                )
+                .with_cache(CachePolicy::NoStore)
+            }
+            Self::Healthz => health::liveness(),
+            Self::Readyz => {
+                let music = state.music();
+                health::readiness(music.loaded.map(|_| music.releases.len()))
+            }
+        }
+    }
+}
+
+fn handle(state: &State, request: Request, listener: Listener) {
+    let started = Instant::now();
+    let (route, reply) = route(state, &request, listener);
+    let lookup = reply.lookup;
+    let mut response = reply.into_response(request.headers());
+    // Added last so they're on every response, including 304s that leave out the rest
+    for header in &state.security_headers {
+        response.add_header(header.clone());
+    }
+    if let Some(hsts) = &state.hsts
+        && http::request_scheme(&request, state.trusted_proxy) == "https"
+    {
+        response.add_header(hsts.clone());
+    }
+    let status = response.status_code().0;
+    let mut entry = access::Entry::new(&request, status, response.data_length(), lookup);
+    respond_or_complain(request, response);
+    entry.latency = started.elapsed();
+    state.requests.observe(route, status, entry.latency);
+    state.access_log.record(&entry);
+}
+
+/// Work out the reply to `request`, along with the name of the route that made it.
+fn route(state: &State, request: &Request, listener: Listener) -> (&'static str, Reply) {
+    const NOT_FOUND: &str = "not_found";
+
+    let method = request.method().to_owned();
+    let url = request.url();
+    // Asks about the server as a whole rather than any path
+    if method == Method::Options && url == "*" {
+        return (
+            "options",
+            Reply::empty(204).with_header(http::header("Allow", ALLOW)),
+        );
+    }
+    let Ok(url) = UriOwned::new(url) else {
+        return (NOT_FOUND, page::error::not_found(url));
+    };
+    let Some(mut path) = url.path.as_deref() else {
+        return (NOT_FOUND, page::error::not_found(""));
+    };
+    path = links::strip_base_path(path);
+    if path.ends_with('/') && path != "/" {
+        path = &path[0..path.len() - 1];
+    }
+
+    let route = Route::find(path);
+    if listener == Listener::Redirect && !route.is_some_and(Route::is_probe) {
+        return ("https_redirect", https_redirect(request, state.https_port));
+    }
+
+    let query = url.as_ref().get_query_parameters().unwrap_or_default();
+
+    let Some(route) = route.filter(|route| route.is_served_on(listener, state)) else {
+        return (NOT_FOUND, page::error::not_found(path));
+    };
+    let reply = match method {
+        Method::Get | Method::Head => route.get(state, path, &query, request),
+        Method::Options => Reply::empty(204).with_header(http::header("Allow", ALLOW)),
+        _ => Reply::empty(405).with_header(http::header("Allow", ALLOW)),
+    };
+    (route.name(), reply)
+}
+
+/// Where to send a request for a document that leaves the slash off the end, so each
+/// one has a single URL. Any query is kept, since previews need theirs.
+fn document_redirect(url: &str, slug: &str) -> Option<String> {
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (url, None),
+    };
+    if path.ends_with('/') {
+        return None;
+    }
+    let link = links::words_document(slug);
+    Some(match query {
+        Some(query) => format!("{link}?{query}"),
+        None => link,
+    })
+}
+
+/// Send the client to the same URL over HTTPS, on the host it asked for.
+fn https_redirect(request: &Request, port: Option<u16>) -> Reply {
+    let Some(host) = http::find_header(request.headers(), "Host") else {
+        return Reply::empty(400);
+    };
+    // Leave out the port the request was made to, but not the end of an IPv6 address
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let port = match port {
+        Some(443) | None => String::new(),
+        Some(port) => format!(":{port}"),
+    };
+    Reply::redirect(308, &format!("https://{host}{port}{}", request.url()))
 }
 
 fn respond_or_complain<R: Read>(req: Request, response: Response<R>) {
     if let Err(e) = req.respond(response) {
-        eprintln!("Failed to respond: {e}");
+        warn!("Failed to respond: {e}");
     }
 }
@@ -0,0 +1,234 @@
+//! Counters for monitoring, exposed in the Prometheus text format.
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write as _},
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering::Relaxed},
+    },
+    time::Duration,
+};
+
+use crate::cache::Stats;
+
+/// Upper bounds of the request latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// How many requests fell into each of [`BUCKETS`], not counting smaller buckets.
+    buckets: [u64; BUCKETS.len()],
+    count:   u64,
+    sum:     f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Requests handled, by route and status.
+#[derive(Default)]
+pub struct Requests(Mutex<BTreeMap<(&'static str, u16), Histogram>>);
+
+impl Requests {
+    pub fn observe(&self, route: &'static str, status: u16, latency: Duration) {
+        let mut requests = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        requests
+            .entry((route, status))
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+}
+
+/// One of the counts in [`Fetches`], picked out so each can be written for every kind.
+type FetchCounter = fn(&Fetches) -> &AtomicU64;
+
+/// Requests made to `MusicBrainz` for one kind of thing.
+pub struct Fetches {
+    kind:     &'static str,
+    attempts: AtomicU64,
+    retries:  AtomicU64,
+    failures: AtomicU64,
+}
+
+impl Fetches {
+    const fn new(kind: &'static str) -> Self {
+        Self {
+            kind,
+            attempts: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn attempt(&self) {
+        self.attempts.fetch_add(1, Relaxed);
+    }
+
+    pub fn retry(&self) {
+        self.retries.fetch_add(1, Relaxed);
+    }
+
+    pub fn failure(&self) {
+        self.failures.fetch_add(1, Relaxed);
+    }
+}
+
+/// These happen while refreshing the music cache, long before there's anything to
+/// hand them to, so they're global.
+pub static RELEASE_GROUP_FETCHES: Fetches = Fetches::new("release_group");
+pub static COVERART_FETCHES: Fetches = Fetches::new("coverart");
+
+/// Everything that's reported, gathered at the time of the scrape.
+pub struct Snapshot<'a> {
+    pub requests:  &'a Requests,
+    pub cache:     Stats,
+    pub releases:  usize,
+    pub documents: usize,
+}
+
+impl Snapshot<'_> {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let requests = self
+            .requests
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        describe(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Requests handled, by route and status.",
+        );
+        for ((route, status), histogram) in requests.iter() {
+            let labels = format!(r#"route="{route}",status="{status}""#);
+            writeln!(out, "http_requests_total{{{labels}}} {}", histogram.count).unwrap();
+        }
+        describe(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time taken to handle requests, by route and status.",
+        );
+        for ((route, status), histogram) in requests.iter() {
+            let name = "http_request_duration_seconds";
+            let labels = format!(r#"route="{route}",status="{status}""#);
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(out, r#"{name}_bucket{{{labels},le="{le}"}} {cumulative}"#).unwrap();
+            }
+            let count = histogram.count;
+            writeln!(out, r#"{name}_bucket{{{labels},le="+Inf"}} {count}"#).unwrap();
+            writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum).unwrap();
+            writeln!(out, "{name}_count{{{labels}}} {count}").unwrap();
+        }
+        drop(requests);
+
+        let cache = self.cache;
+        let lookups = cache.hits + cache.misses;
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = if lookups == 0 {
+            0.0
+        } else {
+            cache.hits as f64 / lookups as f64
+        };
+        let gauges: [(&str, &str, &str, &dyn Display); 8] = [
+            (
+                "render_cache_hits_total",
+                "counter",
+                "Pages served from the render cache.",
+                &cache.hits,
+            ),
+            (
+                "render_cache_misses_total",
+                "counter",
+                "Pages that had to be rendered.",
+                &cache.misses,
+            ),
+            (
+                "render_cache_evictions_total",
+                "counter",
+                "Pages dropped to stay under the limits.",
+                &cache.evictions,
+            ),
+            (
+                "render_cache_hit_ratio",
+                "gauge",
+                "Share of lookups served from the render cache.",
+                &ratio,
+            ),
+            (
+                "render_cache_entries",
+                "gauge",
+                "Pages in the render cache.",
+                &cache.entries,
+            ),
+            (
+                "render_cache_bytes",
+                "gauge",
+                "Size of the pages in the render cache.",
+                &cache.bytes,
+            ),
+            (
+                "music_releases",
+                "gauge",
+                "Releases loaded for the music page.",
+                &self.releases,
+            ),
+            (
+                "words_documents",
+                "gauge",
+                "Documents in the Words content directory.",
+                &self.documents,
+            ),
+        ];
+        for (name, kind, help, value) in gauges {
+            describe(&mut out, name, kind, help);
+            writeln!(out, "{name} {value}").unwrap();
+        }
+
+        let fetches = [&RELEASE_GROUP_FETCHES, &COVERART_FETCHES];
+        let counters: [(&str, &str, FetchCounter); 3] = [
+            (
+                "musicbrainz_fetches_total",
+                "Requests made to MusicBrainz, including retries.",
+                |f| &f.attempts,
+            ),
+            (
+                "musicbrainz_retries_total",
+                "Requests to MusicBrainz that failed and were retried.",
+                |f| &f.retries,
+            ),
+            (
+                "musicbrainz_failures_total",
+                "Fetches from MusicBrainz that gave up.",
+                |f| &f.failures,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            describe(&mut out, name, "counter", help);
+            for fetch in fetches {
+                let value = counter(fetch).load(Relaxed);
+                writeln!(out, r#"{name}{{kind="{}"}} {value}"#, fetch.kind).unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
@@ -0,0 +1,217 @@
+//! Files that Words documents can link to, like images, served from `words/assets`.
+use std::{
+    fs::File,
+    io::{Read as _, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use log::error;
+use percent_encoding::percent_decode_str;
+use tiny_http::Header;
+
+use crate::{
+    http::{Body, CachePolicy, Reply, find_header, header},
+    page::{error, words},
+};
+
+pub const PATH_PREFIX: &str = "/words/assets/";
+
+pub fn assets_dir() -> PathBuf {
+    words::content_dir().join("assets")
+}
+
+pub fn render(path: &str, request_headers: &[Header]) -> Reply {
+    let rest = path.strip_prefix(PATH_PREFIX).unwrap_or_default();
+    let Some(file) = resolve(&assets_dir(), rest) else {
+        return error::not_found(path);
+    };
+    serve(&file, request_headers).unwrap_or_else(|e| {
+        error!("Failed to serve {file:?}: {e}");
+        error::internal()
+    })
+}
+
+/// Map what's left of a URL path onto a file inside of `dir`, refusing anything that
+/// could end up outside of it, including through symlinks. Hidden files aren't served.
+fn resolve(dir: &Path, rest: &str) -> Option<PathBuf> {
+    let rest = percent_decode_str(rest).decode_utf8().ok()?;
+    let mut path = dir.to_path_buf();
+    for component in rest.split('/') {
+        if component.is_empty() || component.starts_with('.') || component.contains(['\\', '\0']) {
+            return None;
+        }
+        path.push(component);
+    }
+
+    let path = path.canonicalize().ok()?;
+    let dir = dir.canonicalize().ok()?;
+    (path.starts_with(&dir) && path.is_file()).then_some(path)
+}
+
+fn serve(path: &Path, request_headers: &[Header]) -> std::io::Result<Reply> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let len = metadata.len();
+    let modified = metadata.modified()?;
+    let etag = format!(
+        r#""{len:x}-{:x}""#,
+        modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    );
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+
+    let mut headers = vec![
+        header("Content-Type", mime.as_ref()),
+        header("Accept-Ranges", "bytes"),
+        header("ETag", &etag),
+    ];
+
+    // A range only applies to the version of the file the client already has part of.
+    let range = find_header(request_headers, "Range")
+        .filter(|_| find_header(request_headers, "If-Range").is_none_or(|tag| tag == etag))
+        .map_or(ByteRange::Full, |range| ByteRange::parse(range, len));
+
+    let (status, body) = match range {
+        ByteRange::Full => (200, Body::Reader(Box::new(file), len as usize)),
+        ByteRange::Partial { start, end } => {
+            file.seek(SeekFrom::Start(start))?;
+            headers.push(header(
+                "Content-Range",
+                &format!("bytes {start}-{end}/{len}"),
+            ));
+            let n = end - start + 1;
+            (206, Body::Reader(Box::new(file.take(n)), n as usize))
+        }
+        ByteRange::Unsatisfiable => {
+            headers.push(header("Content-Range", &format!("bytes */{len}")));
+            (416, Body::Bytes(vec![]))
+        }
+    };
+    // Whether a range fits depends on the request, so the error mustn't be reused
+    let cache = match status {
+        416 => CachePolicy::NoStore,
+        _ => CachePolicy::Public(3600),
+    };
+    Ok(Reply {
+        status,
+        headers,
+        body,
+        cache,
+        last_modified: Some(modified),
+        expires: None,
+        lookup: None,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// Inclusive on both ends, as in the `Range` header.
+    Partial {
+        start: u64,
+        end:   u64,
+    },
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parse a `Range` header for a file that's `len` bytes long. Only a single range
+    /// is supported; anything else gets the whole file, which is always allowed.
+    fn parse(value: &str, len: u64) -> Self {
+        let Some((start, end)) = value
+            .trim()
+            .strip_prefix("bytes=")
+            .filter(|spec| !spec.contains(','))
+            .and_then(|spec| spec.split_once('-'))
+        else {
+            return Self::Full;
+        };
+
+        let (start, end) = match (start.trim(), end.trim()) {
+            ("", "") => return Self::Full,
+            // The last `n` bytes
+            ("", n) => match n.parse::<u64>() {
+                Ok(0) => return Self::Unsatisfiable,
+                Ok(n) => (len.saturating_sub(n), len.saturating_sub(1)),
+                Err(_) => return Self::Full,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return Self::Full;
+                };
+                let end = match end {
+                    "" => len.saturating_sub(1),
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end < start => return Self::Full,
+                        Ok(end) => end.min(len.saturating_sub(1)),
+                        Err(_) => return Self::Full,
+                    },
+                };
+                (start, end)
+            }
+        };
+
+        if len == 0 || start > end || start >= len {
+            Self::Unsatisfiable
+        } else {
+            Self::Partial { start, end }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange::{self, *};
+
+    fn parse(value: &str) -> ByteRange {
+        ByteRange::parse(value, 100)
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse("bytes=0-9"), Partial { start: 0, end: 9 });
+        assert_eq!(parse("bytes=90-"), Partial {
+            start: 90,
+            end:   99,
+        });
+        assert_eq!(parse("bytes=90-1000"), Partial {
+            start: 90,
+            end:   99,
+        });
+        assert_eq!(parse(" bytes= 5 - 5 "), Partial { start: 5, end: 5 });
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse("bytes=-10"), Partial {
+            start: 90,
+            end:   99,
+        });
+        assert_eq!(parse("bytes=-1000"), Partial {
+            start: 0,
+            end:   99,
+        });
+        assert_eq!(parse("bytes=-0"), Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=-10", 0), Unsatisfiable);
+    }
+
+    #[test]
+    fn start_past_the_end() {
+        assert_eq!(parse("bytes=100-"), Unsatisfiable);
+        assert_eq!(parse("bytes=100-200"), Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=0-", 0), Unsatisfiable);
+    }
+
+    #[test]
+    fn anything_else_gets_the_whole_file() {
+        assert_eq!(parse(""), Full);
+        assert_eq!(parse("bytes=-"), Full);
+        assert_eq!(parse("bytes=9-0"), Full);
+        assert_eq!(parse("bytes=0-1,5-6"), Full);
+        assert_eq!(parse("bytes=a-b"), Full);
+        assert_eq!(parse("items=0-9"), Full);
+    }
+}
@@ -0,0 +1,215 @@
+//! Pages for when something can't be shown, so visitors get more than a blank screen.
+//!
+//! The built-in page can be replaced by putting a template in `errors/` in the config
+//! directory, named after the status (e.g. `errors/404.html`), or `errors/default.html`
+//! for any status. These placeholders are filled in:
+//!
+//! - `{{status}}`: the status code, e.g. `404`
+//! - `{{reason}}`: what the status means, e.g. `Not Found`
+//! - `{{message}}`: a sentence explaining what happened
+//! - `{{suggestions}}`: a list of links to pages worth trying instead
+//! - `{{navbar}}`: the navigation bar every page has
+//! - `{{stylesheet}}`: the URL of the stylesheet
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use log::error;
+
+use crate::{
+    config_dir,
+    http::{CachePolicy, Reply},
+    node,
+    page::{escape, links, nav::NAVBAR, words},
+};
+
+/// The most suggestions to show on a page.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Nothing exists for `wanted`, which is whatever the visitor asked for, like a path or
+/// the title of a document. Pages with a similar name are suggested.
+pub fn not_found(wanted: &str) -> Reply {
+    reply(404, not_found_page(wanted))
+}
+
+/// The page behind [`not_found`], also exported as `404.html` for static hosts.
+pub fn not_found_page(wanted: &str) -> String {
+    render(
+        404,
+        "Couldn't find what you were looking for.",
+        &suggest(wanted),
+    )
+}
+
+/// Something went wrong while rendering. Whatever it was should already be logged.
+pub fn internal() -> Reply {
+    reply(
+        500,
+        render(500, "Something went wrong on our end.", &home()),
+    )
+}
+
+fn reply(status: u16, html: String) -> Reply {
+    Reply::new(status, "text/html; charset=utf-8", html).with_cache(CachePolicy::NoStore)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        _ => "Error",
+    }
+}
+
+fn render(status: u16, message: &str, suggestions: &[(String, String)]) -> String {
+    let reason = reason(status);
+    let mut list = String::new();
+    for (title, href) in suggestions {
+        write!(
+            list,
+            "{}",
+            node! {li => node!{a, href = escape(href) => escape(title)}}
+        )
+        .unwrap();
+    }
+    let list = format!("<ul>{list}</ul>");
+
+    match template(status) {
+        Some(template) => template
+            .replace("{{status}}", &status.to_string())
+            .replace("{{reason}}", reason)
+            .replace("{{message}}", &escape(message))
+            .replace("{{suggestions}}", &list)
+            .replace("{{navbar}}", NAVBAR.as_str())
+            .replace("{{stylesheet}}", &links::stylesheet()),
+        None => builtin(status, reason, message, &list),
+    }
+}
+
+/// Read the configured template for `status`, if there is one. It's read every time so
+/// it can be edited without a restart; errors aren't common enough for that to matter.
+fn template(status: u16) -> Option<String> {
+    let dir = config_dir().join("errors");
+    let candidates = [dir.join(format!("{status}.html")), dir.join("default.html")];
+    candidates
+        .iter()
+        .find_map(|path| match fs::read_to_string(path) {
+            Ok(template) => Some(template),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                error!("Failed to read error template {path:?}: {e}");
+                None
+            }
+        })
+}
+
+fn builtin(status: u16, reason: &str, message: &str, suggestions: &str) -> String {
+    let title = format!("{status} {reason}");
+    let html = node! {html, lang = "en-US" =>
+        node!{head =>
+            node!{meta, charset = "utf-8"},
+            node!{meta, name = "viewport", content = "width=device-width, initial-scale=1"},
+            node!{title => title},
+            node!{link, rel = "stylesheet", href = links::stylesheet()},
+        },
+        node!{body, class = "md-body" =>
+            NAVBAR.as_str(),
+            node!{article, class = "md-content-container" =>
+                node!{h1, class = "md-title" => title},
+                node!{p => escape(message)},
+                node!{p => "Maybe one of these?"},
+                suggestions,
+            },
+        },
+    };
+    format!("<!DOCTYPE html>\n{html}")
+}
+
+/// Where to go when there's nothing more specific to suggest.
+fn home() -> Vec<(String, String)> {
+    vec![
+        ("Music Recs".to_string(), links::music(None)),
+        ("Words".to_string(), links::words_index()),
+    ]
+}
+
+/// Where a suggestion links to.
+enum Target {
+    Link(String),
+    /// Looked up among the read documents only once suggested, to find where they're
+    /// served.
+    Document(PathBuf),
+}
+
+/// The pages whose names are closest to `wanted`, or [`home`] if none are close enough
+/// to be worth suggesting.
+fn suggest(wanted: &str) -> Vec<(String, String)> {
+    let wanted = wanted
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let mut candidates = home()
+        .into_iter()
+        .map(|(title, href)| {
+            let name = href.trim_end_matches('/').rsplit('/').next();
+            (
+                name.unwrap_or_default().to_string(),
+                title,
+                Target::Link(href),
+            )
+        })
+        .collect::<Vec<_>>();
+    // File names are compared, so documents that fail to parse still get suggested as
+    // long as they're fixed by the time the link is followed
+    let content = words::find_content(&words::content_dir()).unwrap_or_default();
+    candidates.extend(content.into_iter().filter_map(|path| {
+        let stem = path.file_stem()?.to_string_lossy().into_owned();
+        Some((words::slugify(&stem), stem, Target::Document(path)))
+    }));
+
+    let mut close = candidates
+        .into_iter()
+        .map(|(name, title, target)| (edit_distance(&wanted, &name), title, target))
+        .filter(|(distance, _, _)| *distance <= (wanted.chars().count() / 3).max(2))
+        .collect::<Vec<_>>();
+    close.sort_by_key(|(distance, _, _)| *distance);
+    let documents = words::documents(&words::content_dir()).unwrap_or_default();
+    let suggestions = close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .filter_map(|(_, title, target)| match target {
+            Target::Link(href) => Some((title, href)),
+            Target::Document(path) => {
+                let document = documents.iter().find(|document| document.path == path)?;
+                // Suggesting a document that isn't listed would give it away
+                if !document.meta.is_listed(words::now()) {
+                    return None;
+                }
+                Some((
+                    document.meta.title.clone(),
+                    links::words_document(&document.slug),
+                ))
+            }
+        })
+        .collect::<Vec<_>>();
+    if suggestions.is_empty() {
+        return home();
+    }
+    suggestions
+}
+
+/// The Levenshtein distance between `a` and `b`, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
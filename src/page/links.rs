@@ -0,0 +1,185 @@
+//! Every link between pages is built here so the live site and a static export agree
+//! on where things are.
+use std::sync::OnceLock;
+
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::page::{music, style::STYLESHEET};
+
+/// Everything but the characters that never need escaping in a path segment.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// Links understood by the router, e.g. `/words?title=hello`.
+    Dynamic,
+    /// Directory-style links that map onto files, e.g. `/words/hello/`.
+    Static,
+}
+
+static STYLE: OnceLock<Style> = OnceLock::new();
+
+/// Choose how links are written. This has to happen before anything is rendered, and
+/// can only happen once.
+pub fn set_style(style: Style) {
+    STYLE
+        .set(style)
+        .expect("link style should only be set once, before rendering");
+}
+
+fn style() -> Style {
+    *STYLE.get_or_init(|| Style::Dynamic)
+}
+
+static BASE_PATH: OnceLock<String> = OnceLock::new();
+
+/// Mount every link under `path`, e.g. `/recs` when a proxy serves the site from
+/// there. Like [`set_style`], this can only happen once, before rendering.
+pub fn set_base_path(path: &str) {
+    let path = path.trim_matches('/');
+    let path = if path.is_empty() {
+        String::new()
+    } else {
+        format!("/{path}")
+    };
+    BASE_PATH
+        .set(path)
+        .expect("base path should only be set once, before rendering");
+}
+
+/// The path the site is mounted under, without a trailing slash, so it's empty when
+/// the site is at the root.
+pub fn base_path() -> &'static str {
+    BASE_PATH.get().map_or("", String::as_str)
+}
+
+/// `path` with the base path taken off the front, for proxies that pass it along.
+/// Paths outside of it are left alone, for proxies that take it off themselves.
+pub fn strip_base_path(path: &str) -> &str {
+    strip_prefix_path(base_path(), path)
+}
+
+fn strip_prefix_path<'a>(base_path: &str, path: &'a str) -> &'a str {
+    match path.strip_prefix(base_path) {
+        Some("") => "/",
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
+
+/// A link to `path` on this site, which has to start with `/`.
+pub fn rooted(path: &str) -> String {
+    format!("{}{path}", base_path())
+}
+
+/// Split a URL like `https://example.com/recs` into its origin and path.
+pub fn split_url(url: &str) -> eyre::Result<(&str, &str)> {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        eyre::bail!("{url:?} doesn't start with http:// or https://");
+    };
+    if scheme != "http" && scheme != "https" {
+        eyre::bail!("{url:?} doesn't start with http:// or https://");
+    }
+    let path_start = rest.find('/').map_or(url.len(), |i| scheme.len() + 3 + i);
+    let (origin, path) = url.split_at(path_start);
+    if origin.len() == scheme.len() + 3 {
+        eyre::bail!("{url:?} doesn't have a host");
+    }
+    Ok((origin, path))
+}
+
+pub fn music(sort: Option<&str>) -> String {
+    let path = rooted(music::PATH);
+    match (style(), sort) {
+        (Style::Dynamic, None) => path,
+        (Style::Dynamic, Some(sort)) => format!("{path}/?sort={sort}"),
+        (Style::Static, None) => format!("{path}/"),
+        (Style::Static, Some(sort)) => format!("{path}/sort/{sort}/"),
+    }
+}
+
+pub fn words_index() -> String {
+    match style() {
+        Style::Dynamic => rooted("/words"),
+        Style::Static => rooted("/words/"),
+    }
+}
+
+/// Ends in a slash whatever the style, so relative links in a document go to the same
+/// place when it's served as when it's exported.
+pub fn words_document(slug: &str) -> String {
+    let slug = utf8_percent_encode(slug, SEGMENT);
+    rooted(&format!("/words/{slug}/"))
+}
+
+pub fn words_feed() -> String {
+    rooted("/words/feed.xml")
+}
+
+pub fn words_tag(slug: &str) -> String {
+    let slug = utf8_percent_encode(slug, SEGMENT);
+    match style() {
+        Style::Dynamic => rooted(&format!("/words/tags/{slug}")),
+        Style::Static => rooted(&format!("/words/tags/{slug}/")),
+    }
+}
+
+pub fn words_tag_feed(slug: &str) -> String {
+    let slug = utf8_percent_encode(slug, SEGMENT);
+    rooted(&format!("/words/tags/{slug}/feed.xml"))
+}
+
+pub fn stylesheet() -> String {
+    rooted(&STYLESHEET.path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_url, strip_prefix_path};
+
+    #[test]
+    fn strips_base_path() {
+        assert_eq!(strip_prefix_path("/recs", "/recs"), "/");
+        assert_eq!(strip_prefix_path("/recs", "/recs/"), "/");
+        assert_eq!(strip_prefix_path("/recs", "/recs/words/a"), "/words/a");
+        assert_eq!(strip_prefix_path("", "/words"), "/words");
+    }
+
+    #[test]
+    fn base_path_has_to_be_a_whole_segment() {
+        assert_eq!(strip_prefix_path("/recs", "/recsfoo"), "/recsfoo");
+        assert_eq!(
+            strip_prefix_path("/recs", "/recs-old/words"),
+            "/recs-old/words"
+        );
+        assert_eq!(strip_prefix_path("/recs", "/music"), "/music");
+    }
+
+    #[test]
+    fn splits_urls() {
+        assert_eq!(
+            split_url("https://example.com/recs").unwrap(),
+            ("https://example.com", "/recs")
+        );
+        assert_eq!(
+            split_url("http://[::1]:8000").unwrap(),
+            ("http://[::1]:8000", "")
+        );
+        assert_eq!(
+            split_url("https://example.com/").unwrap(),
+            ("https://example.com", "/")
+        );
+    }
+
+    #[test]
+    fn rejects_bad_urls() {
+        assert!(split_url("example.com/recs").is_err());
+        assert!(split_url("ftp://example.com").is_err());
+        assert!(split_url("https://").is_err());
+        assert!(split_url("https:///recs").is_err());
+    }
+}
@@ -1,5 +1,10 @@
-use crate::{CSS, NAME, page::nav::NAVBAR};
+use crate::{
+    NAME, config_dir, metrics,
+    page::{links, nav::NAVBAR},
+    shutdown,
+};
 use eyre::Context;
+use log::{debug, info, warn};
 use musicbrainz_rs::{
     MusicBrainzClient,
     chrono::NaiveDate,
@@ -11,13 +16,21 @@ use std::{
     fmt::Write,
     fs,
     io::Write as _,
-    path::Path,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 use uri_rs::QueryParameters;
 
 pub const PATH: &str = "/music";
 
+/// The orderings [`render`] understands, along with the label of the button that
+/// links to each one.
+pub const SORTS: &[(&str, &str)] = &[
+    ("artist", "Artist"),
+    ("title", "Album"),
+    ("release_date", "Release Date (Decending)"),
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Release {
     /// `MusicBrainz` `ReleaseGroupID`
@@ -52,55 +65,133 @@ pub struct Recommendation {
     pub highly:  bool,
 }
 
-pub fn prepare() -> eyre::Result<Vec<Release>> {
-    let cache_path = dirs::cache_dir()
+/// How [`refresh`] should treat releases that are already cached.
+#[derive(Debug, Clone, Default)]
+pub struct Refresh {
+    /// Fetch every release again, even if it's already cached.
+    pub force: bool,
+    /// Only (re)fetch this release group, leaving the rest of the cache alone.
+    pub only:  Option<String>,
+}
+
+pub fn cache_path() -> PathBuf {
+    dirs::cache_dir()
         .expect("System should have a cache directory")
         .join(NAME)
-        .join("music.toml");
-    let config_path = dirs::config_dir()
-        .expect("System should have a config directory")
-        .join(NAME)
-        .join("music.toml");
+        .join("music.toml")
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("music.toml")
+}
+
+pub fn prepare() -> eyre::Result<Vec<Release>> {
+    refresh(&Refresh::default())
+}
+
+/// Bring the cache up to date with the configured recommendations, fetching whatever
+/// is missing from `MusicBrainz`.
+pub fn refresh(options: &Refresh) -> eyre::Result<Vec<Release>> {
+    let config_path = config_path();
     let config = load_config(&config_path)?;
-    let cache = load_cache(&cache_path, config)?;
+    if let Some(only) = options.only.as_deref()
+        && !config.recs.iter().any(|rec| rec.release == only)
+    {
+        eyre::bail!("{only:?} isn't one of the recommendations in {config_path:?}");
+    }
+    let cache = load_cache(cache_path(), config, options)?;
     Ok(cache)
 }
 
+/// Get the releases that are already cached, without fetching anything.
+pub fn cached() -> eyre::Result<Vec<Release>> {
+    let config = load_config(config_path())?;
+    let mut releases = read_cache(cache_path())?;
+    releases.retain(|cached| config.recs.iter().any(|rec| rec.release == cached.rgid));
+    Ok(releases)
+}
+
+/// Validate the music config and cache without touching the network. Returns the
+/// number of recommendations that still need to be fetched.
+pub fn check() -> eyre::Result<usize> {
+    let config = load_config(config_path())?;
+    let releases = read_cache(cache_path())?;
+    let missing = config
+        .recs
+        .iter()
+        .filter(|rec| !releases.iter().any(|cached| cached.rgid == rec.release))
+        .count();
+    Ok(missing)
+}
+
 fn load_config(path: impl AsRef<Path>) -> eyre::Result<Config> {
     let path = path.as_ref();
     let contents =
         std::fs::read_to_string(path).context(format!("Failed to read {path:?} to string"))?;
-    let config = toml::from_str(&contents)?;
+    let config =
+        toml::from_str(&contents).context(format!("Failed to parse TOML from {path:?}"))?;
     Ok(config)
 }
 
-fn load_cache(path: impl AsRef<Path>, config: Config) -> eyre::Result<Vec<Release>> {
+fn read_cache(path: impl AsRef<Path>) -> eyre::Result<Vec<Release>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents =
+        fs::read_to_string(path).context(format!("Failed to read {path:?} to string"))?;
+    let cache: Cache =
+        toml::from_str(&contents).context(format!("Failed to parse TOML from {path:?}"))?;
+    Ok(cache.releases)
+}
+
+fn load_cache(
+    path: impl AsRef<Path>,
+    config: Config,
+    options: &Refresh,
+) -> eyre::Result<Vec<Release>> {
     let mut err = None;
     let path = path.as_ref();
-    let mut releases: Vec<Release> = Vec::new();
     let mut client = MusicBrainzClient::default();
 
     client.max_retries = 3;
     client.set_user_agent("Decator's Music Suggestions 0.1.0 (expo-plusplus@proton.me)")?;
 
-    if path.exists() {
-        let contents = fs::read_to_string(path)?;
-        let contents: Cache = toml::from_str(&contents)?;
-        releases = contents.releases;
-    }
-
+    let mut releases = read_cache(path)?;
     releases.retain(|cached| config.recs.iter().any(|rec| rec.release == cached.rgid));
+    // Releases being fetched again are only replaced once that works, so stopping part
+    // way through doesn't lose any
+    let refetch = |rgid: &str| match options.only.as_deref() {
+        Some(only) => only == rgid,
+        None => options.force,
+    };
+
     let mut last_fetch = Instant::now();
-    'outer: for rec in &config.recs {
-        for release in &mut releases {
-            if release.rgid == rec.release {
-                release.highly = rec.highly;
-                continue 'outer;
-            }
+    for rec in &config.recs {
+        let cached = releases
+            .iter()
+            .position(|cached| cached.rgid == rec.release);
+        if let Some(i) = cached
+            && !refetch(&rec.release)
+        {
+            releases[i].highly = rec.highly;
+            continue;
+        }
+        if cached.is_none()
+            && options
+                .only
+                .as_deref()
+                .is_some_and(|only| only != rec.release)
+        {
+            continue;
+        }
+        if shutdown::requested() {
+            warn!("Stopping early, keeping the releases fetched so far");
+            break;
         }
         let now = Instant::now();
         if now - last_fetch < Duration::from_secs(4) {
-            eprintln!("Waiting for rate limit...");
+            debug!("Waiting for rate limit...");
             std::thread::sleep(now - last_fetch);
         }
 
@@ -111,7 +202,7 @@ fn load_cache(path: impl AsRef<Path>, config: Config) -> eyre::Result<Vec<Releas
                 break;
             }
         };
-        eprintln!("Waiting for rate limit...");
+        debug!("Waiting for rate limit...");
         std::thread::sleep(Duration::from_secs(4));
         let artwork = match get_releasegroup_image(&client, &rec.release) {
             Ok(a) => a,
@@ -136,7 +227,10 @@ fn load_cache(path: impl AsRef<Path>, config: Config) -> eyre::Result<Vec<Releas
                 .next(),
             genres: rg.genres.into_iter().flatten().map(|x| x.name).collect(),
         };
-        releases.push(release);
+        match cached {
+            Some(i) => releases[i] = release,
+            None => releases.push(release),
+        }
     }
     releases.sort_by(|a, b| a.title.cmp(&b.title));
 
@@ -149,20 +243,39 @@ fn load_cache(path: impl AsRef<Path>, config: Config) -> eyre::Result<Vec<Releas
             "Failed to create parent directory of cache file: {parent:?}"
         ))?;
     }
-    let mut f = fs::File::create(path)?;
+    // Write the whole thing somewhere else first, so being stopped part way through
+    // can't leave a truncated cache behind
+    let partial = path.with_extension("toml.partial");
+    let mut f = fs::File::create(&partial)?;
     f.write_all(contents.as_bytes())?;
+    f.sync_all()?;
+    fs::rename(&partial, path).context(format!("Failed to replace {path:?}"))?;
 
     if let Some(err) = err {
         return Err(err.into());
     }
-    eprintln!("I have {} releases!", cache.releases.len());
+    info!("I have {} releases!", cache.releases.len());
     Ok(cache.releases)
 }
 
-pub fn render(releases: &[Release], query: &QueryParameters) -> String {
+/// The `sort` query parameter, if it's one of the [`SORTS`]. Nothing else in the
+/// query changes what's rendered.
+pub fn sort_param(query: &QueryParameters) -> Option<&'static str> {
+    let Some(Some(sort)) = query.get("sort") else {
+        return None;
+    };
+    SORTS
+        .iter()
+        .map(|(key, _label)| *key)
+        .find(|key| key == sort)
+}
+
+/// Render the page with the releases in one of the [`SORTS`] orders, or in the order
+/// they're given.
+pub fn render(releases: &[Release], sort: Option<&str>) -> String {
     let mut releases: Vec<_> = releases.iter().collect();
-    if let Some(Some(sort)) = query.get("sort") {
-        match sort.as_str() {
+    if let Some(sort) = sort {
+        match sort {
             "title" => {
                 releases.sort_by(|a, b| a.title.cmp(&b.title));
             }
@@ -202,11 +315,10 @@ fn generate_html(releases: &[&Release]) -> String {
          <meta name="viewport" content="width=device-width, initial-scale=1">
          <title>{TITLE}</title>
          <meta property="og:title" content="{TITLE}" />
-         <style>
-         {CSS}
-         </style>
+         <link rel="stylesheet" href="{}" />
          </head>
-    "#
+    "#,
+        links::stylesheet()
     )
     .unwrap();
 
@@ -233,21 +345,14 @@ fn generate_body(releases: &[&Release]) -> String {
         r#"<div class="label" style="display: inline-block">Sort by:</div>"#
     )
     .unwrap();
-    writeln!(
-        buf,
-        r#"<a class="button" href="{PATH}/?sort=artist">Artist</a>"#
-    )
-    .unwrap();
-    writeln!(
-        buf,
-        r#"<a class="button" href="{PATH}/?sort=title">Album</a>"#
-    )
-    .unwrap();
-    writeln!(
-        buf,
-        r#"<a class="button" href="{PATH}/?sort=release_date">Release Date (Decending)</a>"#
-    )
-    .unwrap();
+    for (sort, label) in SORTS {
+        writeln!(
+            buf,
+            r#"<a class="button" href="{}">{label}</a>"#,
+            links::music(Some(sort))
+        )
+        .unwrap();
+    }
     writeln!(buf, "</div>").unwrap();
 
     writeln!(buf, "<h2>Highly Recommended</h2>").unwrap();
@@ -331,7 +436,8 @@ fn get_releasegroup(
 ) -> Result<ReleaseGroup, musicbrainz_rs::Error> {
     let mut tries = 3i32;
     loop {
-        eprintln!("Getting info for: {id:?}...");
+        info!("Getting info for: {id:?}...");
+        metrics::RELEASE_GROUP_FETCHES.attempt();
         let attempt = ReleaseGroup::fetch()
             .id(id)
             .with_artists()
@@ -348,12 +454,17 @@ fn get_releasegroup(
                     || e.is_request() && !e.is_status() =>
             {
                 if tries < 0 {
+                    metrics::RELEASE_GROUP_FETCHES.failure();
                     break Err(musicbrainz_rs::Error::ReqwestError(e));
                 }
                 tries -= 1;
+                metrics::RELEASE_GROUP_FETCHES.retry();
                 std::thread::sleep(Duration::from_secs(4));
             }
-            Err(e) => break Err(e),
+            Err(e) => {
+                metrics::RELEASE_GROUP_FETCHES.failure();
+                break Err(e);
+            }
         }
     }
 }
@@ -364,7 +475,8 @@ fn get_releasegroup_image(
 ) -> Result<Option<String>, musicbrainz_rs::Error> {
     let mut tries = 3i32;
     loop {
-        eprintln!("Getting image for: {id:?}...");
+        info!("Getting image for: {id:?}...");
+        metrics::COVERART_FETCHES.attempt();
         let attempt = ReleaseGroup::fetch_coverart()
             .id(id)
             .front()
@@ -388,12 +500,17 @@ fn get_releasegroup_image(
                     || e.is_request() && !e.is_status() =>
             {
                 if tries < 0 {
+                    metrics::COVERART_FETCHES.failure();
                     break Err(musicbrainz_rs::Error::ReqwestError(e));
                 }
                 tries -= 1;
+                metrics::COVERART_FETCHES.retry();
                 std::thread::sleep(Duration::from_secs(4));
             }
-            Err(e) => break Err(e),
+            Err(e) => {
+                metrics::COVERART_FETCHES.failure();
+                break Err(e);
+            }
         }
     }
 }
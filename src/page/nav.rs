@@ -1,4 +1,4 @@
-use crate::node;
+use crate::{node, page::links};
 
 lazy_static::lazy_static! {
     pub static ref NAVBAR: String = render();
@@ -7,8 +7,8 @@ lazy_static::lazy_static! {
 pub fn render() -> String {
     node! { nav, class = "navbar" =>
         node! { div, class = "nav-container" =>
-            node!{ a, class="nav-button", href = "/music" => "Music Recs" },
-            node!{ a, class="nav-button", href = "/words" => "Words" },
+            node!{ a, class="nav-button", href = links::music(None) => "Music Recs" },
+            node!{ a, class="nav-button", href = links::words_index() => "Words" },
         },
     }
     .to_string()
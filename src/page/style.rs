@@ -0,0 +1,62 @@
+use std::{fs, sync::Arc};
+
+use log::{error, info};
+
+use crate::{
+    CSS,
+    compress::Encoded,
+    config_dir,
+    http::{Body, CachePolicy, Reply},
+    page::fingerprint,
+};
+
+pub const PATH_PREFIX: &str = "/static/styles.";
+
+lazy_static::lazy_static! {
+    pub static ref STYLESHEET: Stylesheet = Stylesheet::load();
+}
+
+pub struct Stylesheet {
+    /// Compressed ahead of time, since it's the same for every request.
+    pub contents: Arc<Encoded>,
+    /// Where the stylesheet is served from. This changes whenever the contents do, so
+    /// it can be cached forever.
+    pub path:     String,
+}
+
+impl Stylesheet {
+    /// Use `styles.css` from the config directory if there is one, so the site can be
+    /// restyled without a rebuild.
+    fn load() -> Self {
+        let path = config_dir().join("styles.css");
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                info!("Using stylesheet from {path:?}");
+                contents
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CSS.to_string(),
+            Err(e) => {
+                error!("Failed to read {path:?}, using the built-in stylesheet: {e}");
+                CSS.to_string()
+            }
+        };
+        let path = format!("{PATH_PREFIX}{:016x}.css", fingerprint(contents.as_bytes()));
+        Self {
+            contents: Arc::new(Encoded::new(contents.into_bytes(), true)),
+            path,
+        }
+    }
+}
+
+/// Serve the stylesheet for any fingerprinted path, since pages cached from before a
+/// restart may still point at an old one. Only the current path is cached for good.
+pub fn render(path: &str) -> Reply {
+    let stylesheet = &*STYLESHEET;
+    let reply = Reply::new(200, "text/css; charset=utf-8", vec![])
+        .with_body(Body::Shared(Arc::clone(&stylesheet.contents)));
+    if path == stylesheet.path {
+        reply.with_cache(CachePolicy::Immutable)
+    } else {
+        reply
+    }
+}
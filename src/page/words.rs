@@ -1,46 +1,404 @@
 use std::{
+    cmp::Reverse,
+    collections::HashMap,
     fs,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    time::SystemTime,
 };
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, SubsecRound, TimeZone, Utc};
+use chrono_tz::Tz;
 use eyre::Context;
-use log::{error, info};
+use log::{debug, error, info};
 use pulldown_cmark::html;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::fmt::Write as _;
-use tiny_http::{Header, Response, ResponseBox};
 use uri_rs::QueryParameters;
 
-use crate::{CSS, NAME, group_nodes, node, page::nav::NAVBAR};
+use crate::{
+    config_dir, group_nodes,
+    http::{CachePolicy, Reply},
+    node,
+    page::{error, escape, links, nav::NAVBAR},
+};
+
+/// The `[words]` table in `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Leave out documents whose frontmatter can't be read, rather than keeping them as
+    /// drafts so they can still be previewed.
+    pub strict:   bool,
+    /// Where dates without an offset are, and how all of them are shown, named like
+    /// `Europe/Berlin`.
+    pub timezone: Tz,
+    /// Who feeds say wrote the documents. Defaults to the site's host name.
+    pub author:   Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            strict:   false,
+            timezone: Tz::UTC,
+            author:   None,
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
 
-struct Config {}
+/// Like [`links::set_style`], this can only happen once, before rendering.
+pub fn configure(config: Config) {
+    CONFIG
+        .set(config)
+        .expect("words should only be configured once, before rendering");
+}
+
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
 
+/// What a document's frontmatter says about it. Keys that aren't known are errors,
+/// since a misspelt `draft` or `datetime` would otherwise go unnoticed.
 #[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Meta {
+    /// Defaults to the first `# heading`, or else the file name.
+    #[serde(default)]
     pub title:       String,
-    pub datetime:    NaiveDateTime,
+    /// When it's published, as a date like `2024-05-01` or a date and time like
+    /// `2024-05-01T10:00:00`, which can end with an offset like `+02:00`. Without one,
+    /// it's in the configured timezone. Defaults to when the file was last modified.
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub datetime:    DateTime<FixedOffset>,
+    /// When it was last changed enough to mention, written like `datetime`.
+    #[serde(default, deserialize_with = "deserialize_optional_date")]
+    pub updated:     Option<DateTime<FixedOffset>>,
     pub description: Option<String>,
+    /// Where the document is served, as in `/words/{slug}`. Defaults to the file name
+    /// made lowercase, with anything but letters and digits turned into dashes.
+    pub slug:        Option<String>,
+    /// Tags are told apart by their slug, so `Rust` and `rust` are the same tag.
+    #[serde(default)]
+    pub tags:        Vec<String>,
+    /// Only shown to whoever has the preview token.
+    #[serde(default)]
+    pub draft:       bool,
+    /// Served, but left out of the index, feeds and tags, so only those given the link
+    /// find it.
+    #[serde(default)]
+    pub unlisted:    bool,
 }
 
-pub fn render(query: &QueryParameters) -> ResponseBox {
-    let content_dir = dirs::config_dir()
-        .expect("system should have a config dir")
-        .join(NAME)
-        .join("words");
+impl Meta {
+    /// Whether anyone can see the document, rather than only whoever has the preview
+    /// token. Documents dated in the future are published once that time comes.
+    pub fn is_published(&self, now: DateTime<FixedOffset>) -> bool {
+        !self.draft && self.datetime <= now
+    }
+
+    /// Whether the document shows up in the index, feeds and tags.
+    pub fn is_listed(&self, now: DateTime<FixedOffset>) -> bool {
+        self.is_published(now) && !self.unlisted
+    }
+}
 
-    if let Some(Some(title)) = query.get("title") {
-        dbg!(&title);
-        render_document(&content_dir, title)
-    } else {
-        render_index(&content_dir).unwrap_or_else(|e| {
+/// A date as it's written in frontmatter. TOML has dates of its own, but YAML doesn't,
+/// so there they're strings, which are read the same way.
+enum Date {
+    Toml(toml::value::Datetime),
+    Text(String),
+}
+
+impl Date {
+    /// Dates without an offset are in `timezone`.
+    fn resolve(self, timezone: Tz) -> Result<DateTime<FixedOffset>, String> {
+        use toml::value::{Datetime, Offset};
+
+        let datetime = match self {
+            Self::Toml(datetime) => datetime,
+            Self::Text(text) => text.trim().parse::<Datetime>().map_err(|_| {
+                format!(
+                    "{text:?} isn't a date like 2024-05-01, 2024-05-01T10:00:00 or \
+                     2024-05-01T10:00:00+02:00"
+                )
+            })?,
+        };
+        let Some(date) = datetime.date else {
+            return Err(format!("{datetime} doesn't have a date"));
+        };
+        let date = NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())
+            .ok_or_else(|| format!("{datetime} isn't a day that exists"))?;
+        let time = match datetime.time {
+            Some(time) => NaiveTime::from_hms_nano_opt(
+                time.hour.into(),
+                time.minute.into(),
+                time.second.into(),
+                time.nanosecond,
+            )
+            .ok_or_else(|| format!("{datetime} isn't a time that exists"))?,
+            None => NaiveTime::MIN,
+        };
+        let local = date.and_time(time);
+        match datetime.offset {
+            Some(Offset::Z) => Ok(local.and_utc().fixed_offset()),
+            Some(Offset::Custom { minutes }) => FixedOffset::east_opt(i32::from(minutes) * 60)
+                .and_then(|offset| local.and_local_timezone(offset).single())
+                .ok_or_else(|| format!("{datetime} has an offset that's out of range")),
+            None => {
+                // When clocks go back, the first of the two times is the one that's meant
+                timezone
+                    .from_local_datetime(&local)
+                    .earliest()
+                    .map(|datetime| datetime.fixed_offset())
+                    .ok_or_else(|| format!("{datetime} is skipped by the clocks in {timezone}"))
+            }
+        }
+    }
+}
+
+/// Dates are resolved while they're being read, so errors can point at where they are.
+fn deserialize_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<FixedOffset>, D::Error> {
+    struct Visitor;
+
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = DateTime<FixedOffset>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a date")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Self::Value, E> {
+            Date::Text(text.to_string())
+                .resolve(config().timezone)
+                .map_err(E::custom)
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            let datetime = toml::value::Datetime::deserialize(
+                serde::de::value::MapAccessDeserializer::new(map),
+            )?;
+            Date::Toml(datetime)
+                .resolve(config().timezone)
+                .map_err(serde::de::Error::custom)
+        }
+    }
+
+    deserializer.deserialize_any(Visitor)
+}
+
+fn deserialize_optional_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+    deserialize_date(deserializer).map(Some)
+}
+
+/// A document in the content directory, and where it's served.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub path: PathBuf,
+    pub stem: String,
+    pub slug: String,
+    pub meta: Meta,
+}
+
+pub fn content_dir() -> PathBuf {
+    config_dir().join("words")
+}
+
+/// The `preview` query parameter, which shows documents that aren't published when it
+/// matches the configured token.
+pub fn preview_param(query: &QueryParameters) -> Option<&str> {
+    match query.get("preview") {
+        Some(Some(token)) => Some(token.as_str()),
+        _ => None,
+    }
+}
+
+/// The `title` query parameter, which documents used to be found by.
+pub fn title_param(query: &QueryParameters) -> Option<&str> {
+    match query.get("title") {
+        Some(Some(title)) => Some(title.as_str()),
+        _ => None,
+    }
+}
+
+pub fn index() -> Reply {
+    let content_dir = content_dir();
+    match render_index(&content_dir) {
+        Ok(html) => listing(Reply::html(html), &content_dir),
+        Err(e) => {
             error!("Failed to render index: {e}");
-            Response::empty(500).boxed()
-        })
+            error::internal()
+        }
+    }
+}
+
+/// The document served at `/words/{slug}`. Documents that aren't published yet are
+/// only shown for a `preview`, which shouldn't be cached.
+pub fn document(slug: &str, preview: bool) -> Reply {
+    let document = match find_document(&content_dir(), slug) {
+        Ok(Some(document)) if preview || document.meta.is_published(now()) => document,
+        Ok(_) => return error::not_found(slug),
+        Err(e) => {
+            error!("Failed to look for {slug:?}: {e}");
+            return error::internal();
+        }
+    };
+    debug!("Rendering {:?}", document.path);
+    match render_document(&document) {
+        Ok(html) if preview => Reply::html(html).with_cache(CachePolicy::NoStore),
+        Ok(html) => Reply::html(html).with_last_modified(modified(&document.path)),
+        Err(e) => {
+            error!("Failed to render {:?}: {e}", document.path);
+            error::internal()
+        }
+    }
+}
+
+/// Documents used to be at `/words?title={file stem}`, so links to them are sent to
+/// where they are now.
+pub fn redirect(title: &str) -> Reply {
+    let documents = match documents(&content_dir()) {
+        Ok(documents) => documents,
+        Err(e) => {
+            error!("Failed to list documents: {e}");
+            return error::internal();
+        }
+    };
+    let now = now();
+    match documents
+        .iter()
+        .find(|document| document.stem == title && document.meta.is_published(now))
+    {
+        Some(document) => Reply::redirect(301, &links::words_document(&document.slug))
+            .with_cache(CachePolicy::Public(86400)),
+        None => error::not_found(title),
     }
 }
 
-fn find_content(content_dir: &Path) -> eyre::Result<Vec<PathBuf>> {
+/// Feed readers need absolute links, so they start with `origin` if it's known.
+pub fn feed(origin: Option<&str>) -> Reply {
+    let content_dir = content_dir();
+    match render_feed(&content_dir, origin) {
+        Ok(xml) => listing(Reply::new(200, "application/atom+xml", xml), &content_dir),
+        Err(e) => {
+            error!("Failed to render feed: {e}");
+            error::internal()
+        }
+    }
+}
+
+/// The documents tagged with the tag served at `/words/tags/{slug}`.
+pub fn tag(slug: &str) -> Reply {
+    let content_dir = content_dir();
+    match render_tag(&content_dir, slug) {
+        Ok(Some(html)) => listing(Reply::html(html), &content_dir),
+        Ok(None) => error::not_found(slug),
+        Err(e) => {
+            error!("Failed to render tag {slug:?}: {e}");
+            error::internal()
+        }
+    }
+}
+
+/// Like [`feed`], but only with the documents tagged with `slug`.
+pub fn tag_feed(slug: &str, origin: Option<&str>) -> Reply {
+    let content_dir = content_dir();
+    match render_tag_feed(&content_dir, slug, origin) {
+        Ok(Some(xml)) => listing(Reply::new(200, "application/atom+xml", xml), &content_dir),
+        Ok(None) => error::not_found(slug),
+        Err(e) => {
+            error!("Failed to render feed for tag {slug:?}: {e}");
+            error::internal()
+        }
+    }
+}
+
+/// Pages listing documents also change without any file changing, when a scheduled
+/// document is published, so they count that as a modification and expire when the
+/// next one will be.
+fn listing(reply: Reply, content_dir: &Path) -> Reply {
+    let now = now();
+    let documents = documents(content_dir).unwrap_or_default();
+    let published = documents
+        .iter()
+        .filter(|document| document.meta.is_listed(now))
+        .map(|document| SystemTime::from(document.meta.datetime))
+        .max();
+    let scheduled = documents
+        .iter()
+        .filter(|document| !document.meta.draft && document.meta.datetime > now)
+        .map(|document| SystemTime::from(document.meta.datetime))
+        .min();
+    reply
+        .with_last_modified(last_modified(content_dir).max(published))
+        .with_expires(scheduled)
+}
+
+/// The time documents are published by.
+pub fn now() -> DateTime<FixedOffset> {
+    Utc::now().fixed_offset()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// When any of the documents last changed.
+fn last_modified(content_dir: &Path) -> Option<SystemTime> {
+    find_content(content_dir)
+        .ok()?
+        .iter()
+        .filter_map(|path| modified(path))
+        .max()
+}
+
+/// Parse the frontmatter of every document without rendering anything. Returns the
+/// number of documents checked along with a description of each problem found.
+pub fn check(content_dir: &Path) -> eyre::Result<(usize, Vec<String>)> {
+    let content = find_content(content_dir)
+        .context(format!("Failed to list the contents of {content_dir:?}"))?;
+    let mut problems = vec![];
+    let mut served = HashMap::new();
+    for path in &content {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                problems.push(format!("{path:?}: {e}"));
+                continue;
+            }
+        };
+        let meta = match find_frontmatter(&contents).map(|f| f.parse(path)) {
+            Some(Ok(meta)) => meta,
+            Some(Err(e)) => {
+                problems.push(e.to_string());
+                continue;
+            }
+            None => Meta::default(),
+        };
+        for tag in meta.tags.iter().filter(|tag| slugify(tag).is_empty()) {
+            problems.push(format!(
+                "{path:?}: tag {tag:?} has no letters or digits, so it can't be linked to"
+            ));
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let slug = document_slug(meta.slug.as_deref(), &stem);
+        if let Some(other) = served.insert(slug.clone(), path) {
+            problems.push(format!(
+                "{other:?} and {path:?} are both served at {}",
+                links::words_document(&slug)
+            ));
+        }
+    }
+    Ok((content.len(), problems))
+}
+
+pub fn find_content(content_dir: &Path) -> eyre::Result<Vec<PathBuf>> {
     let mut content = vec![];
     for entry in fs::read_dir(content_dir)? {
         let entry = entry?;
@@ -56,80 +414,539 @@ fn find_content(content_dir: &Path) -> eyre::Result<Vec<PathBuf>> {
     Ok(content)
 }
 
-fn render_index(content_dir: &Path) -> eyre::Result<ResponseBox> {
-    let mut index = vec![];
-    let content = find_content(content_dir)?.into_iter().filter_map(|path| {
-        let title = path.with_extension("");
-        let title = title.file_name()?;
+/// The documents in a content directory as they were last read. Reading every one of
+/// them for every page would be slow, and would repeat any errors about them.
+static DOCUMENTS: Mutex<Option<(PathBuf, Arc<Vec<Document>>)>> = Mutex::new(None);
 
-        let mut meta = Meta::default();
-        meta.title = title.to_string_lossy().into_owned();
-        Some((path, meta))
-    });
-    for (path, meta) in content {
-        let contents = fs::read_to_string(&path).context(format!(
-            "Failed to read the entirety of {path:?} into a string"
-        ))?;
-        let (_document, meta) = markdown_to_document(&contents, meta);
-        index.push((meta.title, meta.datetime));
+/// Read the metadata of every document, newest first. Any that can't be read are left
+/// out, so one broken document doesn't take the rest down with it. They're only read
+/// again after [`forget_documents`].
+pub fn documents(content_dir: &Path) -> eyre::Result<Arc<Vec<Document>>> {
+    // Held while reading, so nothing else reads them at the same time
+    let mut read = DOCUMENTS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((dir, documents)) = &*read
+        && dir == content_dir
+    {
+        return Ok(Arc::clone(documents));
+    }
+    let mut documents = find_content(content_dir)?
+        .into_iter()
+        .filter_map(|path| match read_document(path) {
+            Ok(document) => Some(document),
+            Err(e) => {
+                error!("Leaving out a document: {e:#}");
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    documents.sort_by_key(|document| Reverse(document.meta.datetime));
+    let documents = Arc::new(documents);
+    *read = Some((content_dir.to_path_buf(), Arc::clone(&documents)));
+    Ok(documents)
+}
+
+/// Read the documents again next time they're needed, since something changed.
+pub fn forget_documents() {
+    *DOCUMENTS.lock().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// The documents that show up in the index, feeds and tags, newest first.
+pub fn listed(content_dir: &Path) -> eyre::Result<Vec<Document>> {
+    let now = now();
+    Ok(documents(content_dir)?
+        .iter()
+        .filter(|document| document.meta.is_listed(now))
+        .cloned()
+        .collect())
+}
+
+/// Read the metadata of the document at `path` without rendering it, filling in
+/// whatever its frontmatter leaves out. Frontmatter that can't be parsed might have
+/// been hiding the document, so it's treated as a draft, or in strict mode an error.
+pub fn read_document(path: PathBuf) -> eyre::Result<Document> {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let contents = fs::read_to_string(&path).context(format!(
+        "Failed to read the entirety of {path:?} into a string"
+    ))?;
+    let mut meta = match find_frontmatter(&contents).map(|f| f.parse(&path)) {
+        Some(Ok(meta)) => meta,
+        Some(Err(e)) if config().strict => return Err(e),
+        Some(Err(e)) => {
+            error!("Treating a document as a draft until its metadata can be parsed: {e}");
+            Meta {
+                draft: true,
+                ..Meta::default()
+            }
+        }
+        None => Meta::default(),
+    };
+    if meta.title.is_empty() {
+        meta.title = first_heading(&contents).unwrap_or_else(|| stem.clone());
+    }
+    if meta.datetime == DateTime::<Utc>::UNIX_EPOCH {
+        meta.datetime = modified(&path)
+            .map(|modified| {
+                DateTime::<Utc>::from(modified)
+                    .trunc_subsecs(0)
+                    .fixed_offset()
+            })
+            .unwrap_or_default();
+    }
+    Ok(Document {
+        path,
+        slug: document_slug(meta.slug.as_deref(), &stem),
+        stem,
+        meta,
+    })
+}
+
+/// Where a document is served, from the `slug` in its frontmatter or else its file stem.
+fn document_slug(slug: Option<&str>, stem: &str) -> String {
+    // Anything that slugifies to nothing would leave the document without an address
+    [slug, Some(stem)]
+        .into_iter()
+        .flatten()
+        .map(slugify)
+        .find(|slug| !slug.is_empty())
+        .unwrap_or_else(|| stem.to_string())
+}
+
+/// The document served at `/words/{slug}`, if there is one.
+pub fn find_document(content_dir: &Path, slug: &str) -> eyre::Result<Option<Document>> {
+    Ok(documents(content_dir)?
+        .iter()
+        .find(|document| document.slug == slug)
+        .cloned())
+}
+
+/// A tag, and how many documents have it.
+#[derive(Debug, Clone)]
+pub struct Tag {
+    /// How it's written in the first document found with it.
+    pub name:  String,
+    pub slug:  String,
+    pub count: usize,
+}
+
+/// Every tag on `documents`, in alphabetical order.
+pub fn tags(documents: &[Document]) -> Vec<Tag> {
+    let mut tags = HashMap::<String, Tag>::new();
+    for document in documents {
+        for (name, slug) in unique_tags(&document.meta.tags) {
+            tags.entry(slug.clone())
+                .or_insert(Tag {
+                    name: name.to_string(),
+                    slug,
+                    count: 0,
+                })
+                .count += 1;
+        }
+    }
+    let mut tags = tags.into_values().collect::<Vec<_>>();
+    tags.sort_by_key(|tag| tag.name.to_lowercase());
+    tags
+}
+
+/// Each of `tags` with its slug, only the first time it's written, and leaving out any
+/// that slugify to nothing since there'd be nowhere to link them.
+fn unique_tags(tags: &[String]) -> Vec<(&str, String)> {
+    let mut unique = Vec::<(&str, String)>::new();
+    for tag in tags {
+        let slug = slugify(tag);
+        if !slug.is_empty() && unique.iter().all(|(_, other)| *other != slug) {
+            unique.push((tag, slug));
+        }
+    }
+    unique
+}
+
+/// The documents tagged with `slug`, along with the tag, or `None` if nothing is.
+fn tagged(documents: Vec<Document>, slug: &str) -> Option<(Tag, Vec<Document>)> {
+    let documents = documents
+        .into_iter()
+        .filter(|document| {
+            unique_tags(&document.meta.tags)
+                .iter()
+                .any(|(_, tag)| tag == slug)
+        })
+        .collect::<Vec<_>>();
+    let tag = tags(&documents).into_iter().find(|tag| tag.slug == slug)?;
+    Some((tag, documents))
+}
+
+/// Lowercase letters and digits, with whatever's between them replaced by a dash.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
     }
-    index.sort_by_key(|(_, t)| *t);
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+pub fn render_index(content_dir: &Path) -> eyre::Result<String> {
+    let documents = listed(content_dir)?;
+    let cloud = tag_cloud(&tags(&documents));
+    Ok(render_listing(
+        "Words",
+        &links::words_feed(),
+        &cloud,
+        &documents,
+    ))
+}
+
+/// The page listing the documents tagged with `slug`, or `None` if nothing is.
+pub fn render_tag(content_dir: &Path, slug: &str) -> eyre::Result<Option<String>> {
+    let Some((tag, documents)) = tagged(listed(content_dir)?, slug) else {
+        return Ok(None);
+    };
+    Ok(Some(render_listing(
+        &format!("Words tagged {}", tag.name),
+        &links::words_tag_feed(slug),
+        "",
+        &documents,
+    )))
+}
 
-    const TITLE: &str = "Words";
-    let rest_html = node! {html, lang = "en-US" =>
+/// A page listing `documents`, with `header` above them.
+fn render_listing(title: &str, feed: &str, header: &str, documents: &[Document]) -> String {
+    let title = escape(title);
+    let html = node! {html, lang = "en-US" =>
         node!{head =>
             node!{meta, charset = "utf-8"},
             node!{meta, name = "viewport", content = "width=device-width, initial-scale=1"},
-            node!{title => TITLE},
-            node!{meta, property="og:title", content=TITLE},
-            node!{style => CSS}
+            node!{title => &title},
+            node!{meta, property="og:title", content=&title},
+            format!(
+                r#"<link rel="alternate" type="application/atom+xml" title="{title}" href="{}" />"#,
+                escape(feed)
+            ),
+            node!{link, rel = "stylesheet", href = links::stylesheet()},
         },
         node!{body =>
             NAVBAR.as_str(),
-            node!{ol =>
-                index.iter().fold(String::new(), |acc, (title, _datetime)| {
-                    let x = node!{li => node!{a, href = format!("/words?title={title}") => title}};
-                    format!("{acc}{x}")
-                })
+            node!{main, class = "md-content-container" =>
+                header,
+                node!{ol, class = "words-index" =>
+                    documents.iter().fold(String::new(), |acc, document| {
+                        format!("{acc}{}", index_entry(document))
+                    })
+                }
             }
         }
     };
-    let html = format!("<!DOCTYPE html>\n{rest_html}");
+    format!("<!DOCTYPE html>\n{html}")
+}
 
-    let response = Response::from_string(html)
-        .with_header(
-            "Content-Type: text/html"
-                .parse::<Header>()
-                .expect("vaild header"),
-        )
-        .boxed();
-    Ok(response)
+/// `datetime` as a day in the configured timezone, along with the exact time for
+/// machines. Nothing is shown if it's unknown.
+fn time_element(datetime: &DateTime<FixedOffset>) -> String {
+    if *datetime == DateTime::<Utc>::UNIX_EPOCH {
+        return String::new();
+    }
+    let local = datetime.with_timezone(&config().timezone);
+    node! {time, datetime = datetime.to_rfc3339() => local.format("%B %-d, %Y")}.to_string()
 }
 
-fn render_document(content_dir: &Path, title: &str) -> ResponseBox {
-    let meta = Meta::default();
-    let path = content_dir.join(format!("{title}.md"));
-    if !path.exists() || title.contains('/') {
-        return Response::empty(404).boxed();
+/// Links to every tag, bigger the more documents have it.
+fn tag_cloud(tags: &[Tag]) -> String {
+    if tags.is_empty() {
+        return String::new();
     }
-    let (document, _meta) = match fs::read_to_string(&path) {
-        Ok(contents) => markdown_to_document(&contents, meta),
-        Err(e) => {
-            error!("Failed to read the enirety of {path:?} into a string: {e}");
-            return Response::empty(500).boxed();
+    let most = tags.iter().map(|tag| tag.count).max().unwrap_or(1);
+    let links = tags.iter().fold(String::new(), |acc, tag| {
+        let size = 0.85 + 0.65 * (tag.count - 1) as f32 / (most - 1).max(1) as f32;
+        let x = node! {a,
+            href = escape(&links::words_tag(&tag.slug)),
+            style = format!("font-size: {size:.2}em"),
+            title = match tag.count {
+                1 => "1 document".to_string(),
+                n => format!("{n} documents"),
+            } =>
+            escape(&tag.name)
+        };
+        format!("{acc}{x} ")
+    });
+    node! {nav, class = "tag-cloud" => links}.to_string()
+}
+
+/// A link to each of `tags`, shown with a document.
+fn tag_chips(tags: &[String]) -> String {
+    let chips = unique_tags(tags)
+        .into_iter()
+        .fold(String::new(), |acc, (tag, slug)| {
+            let x = node! {li =>
+                node!{a, class = "tag", href = escape(&links::words_tag(&slug)) => escape(tag)}
+            };
+            format!("{acc}{x}")
+        });
+    if chips.is_empty() {
+        return chips;
+    }
+    node! {ul, class = "tags" => chips}.to_string()
+}
+
+fn index_entry(document: &Document) -> String {
+    let meta = &document.meta;
+    let date = time_element(&meta.datetime);
+    let description = meta
+        .description
+        .as_deref()
+        .map_or_default(|description| node! {p => escape(description)}.to_string());
+    node! {li =>
+        node!{h2, class = "md-title" =>
+            node!{a, href = escape(&links::words_document(&document.slug)) => escape(&meta.title)}
+        },
+        date,
+        description,
+    }
+    .to_string()
+}
+
+/// Render an Atom feed of every document, newest first, with links starting with
+/// `origin`, e.g. `https://example.com`.
+pub fn render_feed(content_dir: &Path, origin: Option<&str>) -> eyre::Result<String> {
+    render_feed_of(
+        "Words",
+        links::words_index(),
+        links::words_feed(),
+        &listed(content_dir)?,
+        origin,
+    )
+}
+
+/// Like [`render_feed`], but only with the documents tagged with `slug`, or `None` if
+/// nothing is.
+pub fn render_tag_feed(
+    content_dir: &Path,
+    slug: &str,
+    origin: Option<&str>,
+) -> eyre::Result<Option<String>> {
+    let Some((tag, documents)) = tagged(listed(content_dir)?, slug) else {
+        return Ok(None);
+    };
+    render_feed_of(
+        &format!("Words tagged {}", tag.name),
+        links::words_tag(slug),
+        links::words_tag_feed(slug),
+        &documents,
+        origin,
+    )
+    .map(Some)
+}
+
+/// The feed of `documents`, where `page` is the page listing them.
+fn render_feed_of(
+    title: &str,
+    page: String,
+    feed: String,
+    documents: &[Document],
+    origin: Option<&str>,
+) -> eyre::Result<String> {
+    let absolute = |link: String| escape(&format!("{}{link}", origin.unwrap_or_default()));
+    let updated = documents
+        .iter()
+        .map(|document| document.meta.updated.unwrap_or(document.meta.datetime))
+        .max()
+        .unwrap_or_default();
+
+    let mut buf = String::new();
+    writeln!(buf, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(buf, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(buf, "<title>{}</title>", escape(title))?;
+    let index = absolute(page);
+    writeln!(buf, r#"<link href="{index}" />"#)?;
+    writeln!(buf, r#"<link rel="self" href="{}" />"#, absolute(feed))?;
+    writeln!(buf, "<id>{index}</id>")?;
+    writeln!(buf, "<updated>{}</updated>", updated.to_rfc3339())?;
+    // Atom requires an author, which every entry shares
+    let host = origin
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_, host)| host);
+    let author = config().author.as_deref().or(host).unwrap_or("Words");
+    writeln!(buf, "<author><name>{}</name></author>", escape(author))?;
+    for Document { slug, meta, .. } in documents {
+        let link = absolute(links::words_document(slug));
+        writeln!(buf, "<entry>")?;
+        writeln!(buf, "<title>{}</title>", escape(&meta.title))?;
+        writeln!(buf, r#"<link href="{link}" />"#)?;
+        writeln!(buf, "<id>{link}</id>")?;
+        writeln!(buf, "<published>{}</published>", meta.datetime.to_rfc3339())?;
+        writeln!(
+            buf,
+            "<updated>{}</updated>",
+            meta.updated.unwrap_or(meta.datetime).to_rfc3339()
+        )?;
+        if let Some(description) = meta.description.as_deref() {
+            writeln!(buf, "<summary>{}</summary>", escape(description))?;
+        }
+        for (tag, _) in unique_tags(&meta.tags) {
+            writeln!(buf, r#"<category term="{}" />"#, escape(tag))?;
+        }
+        writeln!(buf, "</entry>")?;
+    }
+    writeln!(buf, "</feed>")?;
+    Ok(buf)
+}
+
+pub fn render_document(document: &Document) -> eyre::Result<String> {
+    let path = &document.path;
+    let contents = fs::read_to_string(path).context(format!(
+        "Failed to read the enirety of {path:?} into a string"
+    ))?;
+    Ok(markdown_to_document(&contents, &document.meta))
+}
+
+/// What frontmatter is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Yaml,
+}
+
+impl From<pulldown_cmark::MetadataBlockKind> for Format {
+    fn from(kind: pulldown_cmark::MetadataBlockKind) -> Self {
+        match kind {
+            pulldown_cmark::MetadataBlockKind::PlusesStyle => Self::Toml,
+            pulldown_cmark::MetadataBlockKind::YamlStyle => Self::Yaml,
+        }
+    }
+}
+
+/// Frontmatter as it's written in a document.
+struct Frontmatter {
+    format: Format,
+    text:   String,
+    /// The line of the document it starts on, counting from 1.
+    line:   usize,
+}
+
+impl Frontmatter {
+    /// Parse it, with errors pointing at the line of `path` they're about.
+    fn parse(&self, path: &Path) -> eyre::Result<Meta> {
+        let (message, offset) = match self.format {
+            Format::Toml => match toml::de::from_str(&self.text) {
+                Ok(meta) => return Ok(meta),
+                Err(e) => (e.message().to_string(), e.span().map(|span| span.start)),
+            },
+            Format::Yaml => match serde_yaml_ng::from_str(&self.text) {
+                Ok(meta) => return Ok(meta),
+                Err(e) => {
+                    // The message ends with where in the frontmatter the error is, which
+                    // is replaced with where in the document it is
+                    let message = e.to_string();
+                    let message = message.split(" at line ").next().unwrap_or_default();
+                    (message.to_string(), e.location().map(|at| at.index()))
+                }
+            },
+        };
+        let before = offset.and_then(|offset| self.text.get(..offset));
+        let line = self.line + before.map_or(0, |before| before.matches('\n').count());
+        eyre::bail!("{}:{line}: {message}", path.display())
+    }
+}
+
+/// Find the frontmatter, if there is any.
+fn find_frontmatter(contents: &str) -> Option<Frontmatter> {
+    use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+
+    if let Some((frontmatter, _)) = leading_frontmatter(contents) {
+        return Some(frontmatter);
+    }
+    let mut parser = Parser::new(contents).into_offset_iter();
+    let start = parser.find_map(|(event, range)| match event {
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang)))
+            if lang.trim() == "frontmatter" =>
+        {
+            Some(range.start)
+        }
+        _ => None,
+    })?;
+    let mut text = String::new();
+    for (event, _) in parser {
+        match event {
+            Event::Text(t) => text.push_str(&t),
+            Event::End(TagEnd::CodeBlock) => break,
+            _ => {}
         }
+    }
+    Some(Frontmatter {
+        format: Format::Toml,
+        text,
+        // It starts on the line after the one that opens it
+        line: contents[..start].matches('\n').count() + 2,
+    })
+}
+
+/// A `+++` or `---` block at the start of a document, which is how other tools write
+/// frontmatter, along with where the rest of the document starts. pulldown-cmark finds
+/// these blocks anywhere, but past the start they're thematic breaks and headings.
+fn leading_frontmatter(contents: &str) -> Option<(Frontmatter, usize)> {
+    use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+    let options = Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+    let mut parser = Parser::new_ext(contents, options).into_offset_iter();
+    let (Event::Start(Tag::MetadataBlock(kind)), range) = parser.next()? else {
+        return None;
     };
+    let mut text = String::new();
+    for (event, end) in parser {
+        match event {
+            Event::Text(t) => text.push_str(&t),
+            Event::End(TagEnd::MetadataBlock(_)) => {
+                let frontmatter = Frontmatter {
+                    format: kind.into(),
+                    text,
+                    line: contents[..range.start].matches('\n').count() + 2,
+                };
+                return Some((frontmatter, end.end));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The document without the `+++` or `---` frontmatter it starts with, if any.
+fn body(contents: &str) -> &str {
+    leading_frontmatter(contents).map_or(contents, |(_, start)| &contents[start..])
+}
 
-    Response::from_string(document)
-        .with_header(
-            "Content-Type: text/html"
-                .parse::<Header>()
-                .expect("vaild header"),
+/// The text of the first `# heading`, for documents without a title.
+fn first_heading(contents: &str) -> Option<String> {
+    use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+
+    let mut parser = Parser::new(body(contents));
+    parser.find(|event| {
+        matches!(
+            event,
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            })
         )
-        .boxed()
+    })?;
+    let mut heading = String::new();
+    for event in parser {
+        match event {
+            Event::Text(text) | Event::Code(text) => heading.push_str(&text),
+            Event::End(TagEnd::Heading(_)) => break,
+            _ => {}
+        }
+    }
+    Some(heading.trim().to_string()).filter(|heading| !heading.is_empty())
 }
 
-fn markdown_to_document(contents: &str, mut meta: Meta) -> (String, Meta) {
+/// Frontmatter is left out, since `meta` was already read from it.
+fn markdown_to_document(contents: &str, meta: &Meta) -> String {
     use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
     use std::sync::LazyLock;
     use syntect::{
@@ -150,8 +967,9 @@ fn markdown_to_document(contents: &str, mut meta: Meta) -> (String, Meta) {
         Highlight,
     }
 
-    let mut options = Options::empty();
-    options.insert(Options::ENABLE_GFM);
+    // Frontmatter in a fence is skipped along the way, but any at the start isn't text
+    let contents = body(contents);
+    let options = Options::ENABLE_GFM;
 
     let mut state = ParseState::default();
     let mut code = String::new();
@@ -171,15 +989,32 @@ fn markdown_to_document(contents: &str, mut meta: Meta) -> (String, Meta) {
                 Some(Event::Html(r#"<div class="md-codeblock">"#.into()))
             }
         }
+        // Links to elsewhere on the site have to stay under the base path
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Some(Event::Start(Tag::Link {
+            link_type,
+            dest_url: rooted_url(dest_url),
+            title,
+            id,
+        })),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Some(Event::Start(Tag::Image {
+            link_type,
+            dest_url: rooted_url(dest_url),
+            title,
+            id,
+        })),
         Event::Text(text) => match state {
             ParseState::Normal => Some(Event::Text(text)),
-            ParseState::Meta => {
-                match toml::de::from_str::<Meta>(&text) {
-                    Ok(m) => meta = m,
-                    Err(e) => error!("Failed to parse metadata: {e}"),
-                }
-                None
-            }
+            ParseState::Meta => None,
             ParseState::Highlight => {
                 code.push_str(&text);
                 None
@@ -208,12 +1043,20 @@ fn markdown_to_document(contents: &str, mut meta: Meta) -> (String, Meta) {
     let mut html = String::new();
     pulldown_cmark::html::push_html(&mut html, parser);
 
-    let html = apply_document_template(&html, &meta);
-    (html, meta)
+    apply_document_template(&html, meta)
+}
+
+/// Put URLs that start with `/` under the base path, leaving everything else alone.
+fn rooted_url(url: pulldown_cmark::CowStr<'_>) -> pulldown_cmark::CowStr<'_> {
+    if url.starts_with('/') && !url.starts_with("//") {
+        links::rooted(&url).into()
+    } else {
+        url
+    }
 }
 
 fn apply_document_template(html: &str, meta: &Meta) -> String {
-    let title = meta.title.as_str();
+    let title = escape(&meta.title);
 
     let mut out = String::new();
 
@@ -221,27 +1064,36 @@ fn apply_document_template(html: &str, meta: &Meta) -> String {
         node!{head =>
             node!{meta, charset = "utf-8"},
             node!{meta, name = "viewport", content = "width=device-width, initial-scale=1"},
-            node!{title => title},
-            node!{meta, property="og:title", content=title},
-            meta.description.as_deref().map_or_default(|description| {
+            node!{title => &title},
+            node!{meta, property="og:title", content=&title},
+            if meta.is_listed(now()) {
+                String::new()
+            } else {
+                node!{meta, name = "robots", content = "noindex"}.to_string()
+            },
+            meta.description.as_deref().map(escape).map_or_default(|description| {
                 group_nodes!(
                     node!(meta, name = "description", content = description),
                     node!(meta, name = "description", content = description)
                 ).to_string()
             }),
-            node!{style => CSS},
+            node!{link, rel = "stylesheet", href = links::stylesheet()},
         },
         node!{body, class ="md-body" =>
             NAVBAR.as_str(),
             node!{article, class="md-content-container" =>
                 node!{div, style = "display: flex; justify-content: space-between; aligin-items: center; margin: 0" =>
                     node! {h1, class = "md-title", style = "margin: 0; margin-bottom: 0.17ex" =>
-                        title
+                        &title
                     },
                     node!{span, style = "font-size: x-small; color: var(--text-alt)" =>
-                        meta.datetime
+                        time_element(&meta.datetime),
+                        meta.updated.as_ref().map_or_default(|updated| {
+                            format!(", updated {}", time_element(updated))
+                        })
                     }
                 },
+                tag_chips(&meta.tags),
                 node!{hr, style = "margin-bottom:2ex"},
                 html,
             },
@@ -250,3 +1102,255 @@ fn apply_document_template(html: &str, meta: &Meta) -> String {
 
     format!("<!DOCTYPE html>\n{html}")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::{DateTime, FixedOffset};
+    use chrono_tz::Tz;
+
+    use super::{
+        Date, Format, Meta, body, document_slug, find_frontmatter, first_heading, slugify,
+    };
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello World"), "hello-world");
+        assert_eq!(slugify("  --Hello,  World!--  "), "hello-world");
+        assert_eq!(slugify("Café Notes"), "café-notes");
+        assert_eq!(slugify("Rust 2024: What's New?"), "rust-2024-what-s-new");
+        assert_eq!(slugify("ÜBER"), "über");
+        assert_eq!(slugify("!!"), "");
+    }
+
+    #[test]
+    fn document_slugs() {
+        assert_eq!(document_slug(Some("Custom Slug"), "file"), "custom-slug");
+        assert_eq!(document_slug(None, "My File"), "my-file");
+        // Slugs with nothing left fall back to the file name, and then to it as is
+        assert_eq!(document_slug(Some("!!"), "file"), "file");
+        assert_eq!(document_slug(Some("!!"), "??"), "??");
+    }
+
+    fn resolve(text: &str, timezone: Tz) -> Result<DateTime<FixedOffset>, String> {
+        Date::Text(text.to_string()).resolve(timezone)
+    }
+
+    fn rfc3339(text: &str, timezone: Tz) -> String {
+        resolve(text, timezone).unwrap().to_rfc3339()
+    }
+
+    #[test]
+    fn date_only() {
+        assert_eq!(rfc3339("2024-05-01", Tz::UTC), "2024-05-01T00:00:00+00:00");
+        assert_eq!(
+            rfc3339("2024-05-01", Tz::Europe__Berlin),
+            "2024-05-01T00:00:00+02:00"
+        );
+        assert_eq!(
+            rfc3339("2024-01-01", Tz::Europe__Berlin),
+            "2024-01-01T00:00:00+01:00"
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        let berlin = Tz::Europe__Berlin;
+        assert_eq!(
+            rfc3339("2024-05-01T10:00:00Z", berlin),
+            "2024-05-01T10:00:00+00:00"
+        );
+        assert_eq!(
+            rfc3339("2024-05-01T10:00:00-05:30", berlin),
+            "2024-05-01T10:00:00-05:30"
+        );
+        assert_eq!(
+            rfc3339("2024-05-01 10:00:00", berlin),
+            "2024-05-01T10:00:00+02:00"
+        );
+        assert_eq!(
+            rfc3339("2024-05-01T10:00:00.5+01:00", berlin),
+            "2024-05-01T10:00:00.500+01:00"
+        );
+    }
+
+    #[test]
+    fn toml_dates() {
+        // Without a configured timezone, dates are in UTC
+        let resolve = |toml: &str| {
+            let meta: Meta = toml::from_str(toml).unwrap();
+            meta.datetime.to_rfc3339()
+        };
+        assert_eq!(
+            resolve("datetime = 2024-05-01"),
+            "2024-05-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            resolve("datetime = 2024-05-01T10:00:00+02:00"),
+            "2024-05-01T10:00:00+02:00"
+        );
+        assert_eq!(
+            resolve(r#"datetime = "2024-05-01T10:00:00""#),
+            "2024-05-01T10:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn dst() {
+        let berlin = Tz::Europe__Berlin;
+        // Clocks skip from 02:00 to 03:00
+        assert!(resolve("2024-03-31T02:30:00", berlin).is_err());
+        assert_eq!(
+            rfc3339("2024-03-31T03:30:00", berlin),
+            "2024-03-31T03:30:00+02:00"
+        );
+        // And go back from 03:00 to 02:00, so 02:30 happens twice
+        assert_eq!(
+            rfc3339("2024-10-27T02:30:00", berlin),
+            "2024-10-27T02:30:00+02:00"
+        );
+        // Unless it says which one it is
+        assert_eq!(
+            rfc3339("2024-03-31T02:30:00+01:00", berlin),
+            "2024-03-31T02:30:00+01:00"
+        );
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_date() {
+        assert!(resolve("June 5th", Tz::UTC).is_err());
+        assert!(resolve("2024-02-30", Tz::UTC).is_err());
+        assert!(resolve("10:00:00", Tz::UTC).is_err());
+        assert!(resolve("", Tz::UTC).is_err());
+    }
+
+    /// The format, text and first line of the frontmatter in `contents`.
+    fn frontmatter(contents: &str) -> Option<(Format, String, usize)> {
+        find_frontmatter(contents).map(|f| (f.format, f.text, f.line))
+    }
+
+    #[test]
+    fn leading_blocks() {
+        assert_eq!(
+            frontmatter("+++\ntitle = 'A'\n+++\n# Hi"),
+            Some((Format::Toml, "title = 'A'\n".to_string(), 2))
+        );
+        assert_eq!(
+            frontmatter("---\ntitle: A\n---\n# Hi"),
+            Some((Format::Yaml, "title: A\n".to_string(), 2))
+        );
+        assert_eq!(
+            frontmatter("---\ntitle: A\n...\n# Hi"),
+            Some((Format::Yaml, "title: A\n".to_string(), 2))
+        );
+        assert_eq!(body("---\ntitle: A\n---\n# Hi"), "\n# Hi");
+    }
+
+    #[test]
+    fn fenced() {
+        assert_eq!(
+            frontmatter("```frontmatter\ntitle = 'A'\ndraft = true\n```\n"),
+            Some((Format::Toml, "title = 'A'\ndraft = true\n".to_string(), 2))
+        );
+        // Unlike the blocks, the fence can come after the heading
+        assert_eq!(
+            frontmatter("# Hi\n\n```frontmatter\ntitle = 'A'\n```\n"),
+            Some((Format::Toml, "title = 'A'\n".to_string(), 4))
+        );
+        assert_eq!(frontmatter("```rust\nfn main() {}\n```\n"), None);
+    }
+
+    #[test]
+    fn blocks_only_come_first() {
+        // A thematic break and a heading, not frontmatter
+        let contents = "Intro.\n\n---\nMore: text\n---\n";
+        assert_eq!(frontmatter(contents), None);
+        assert_eq!(body(contents), contents);
+        assert_eq!(frontmatter("# Hi\n\n+++\ntitle = 'A'\n+++\n"), None);
+
+        // Only the first is frontmatter, with the rest left as text
+        let contents = "---\ntitle: A\n---\nIntro.\n\n---\nMore text\n---\n";
+        assert_eq!(body(contents), "\nIntro.\n\n---\nMore text\n---\n");
+    }
+
+    #[test]
+    fn unfinished_blocks() {
+        assert_eq!(frontmatter("---\ntitle: A\n"), None);
+        assert_eq!(frontmatter("+++\ntitle = 'A'\n"), None);
+        assert_eq!(frontmatter("---\n\ntitle: A\n---\n"), None);
+        assert_eq!(frontmatter(""), None);
+    }
+
+    #[test]
+    fn first_headings() {
+        assert_eq!(
+            first_heading("---\ntitle: A\n---\n# Hi `there`\n"),
+            Some("Hi there".to_string())
+        );
+        assert_eq!(
+            first_heading("## Not this\n\n# This\n"),
+            Some("This".to_string())
+        );
+        assert_eq!(first_heading("Just text.\n"), None);
+    }
+
+    /// Where parsing the frontmatter in `contents` says it went wrong.
+    fn error_line(contents: &str) -> String {
+        let frontmatter = find_frontmatter(contents).unwrap();
+        let error = frontmatter.parse(Path::new("words/a.md")).unwrap_err();
+        let error = error.to_string();
+        error.split(": ").next().unwrap().to_string()
+    }
+
+    #[test]
+    fn toml_errors_have_lines() {
+        assert_eq!(
+            error_line("+++\ntitle = 'A'\ndraft = 3\n+++\n"),
+            "words/a.md:3"
+        );
+        assert_eq!(
+            error_line("+++\ntitle = 'A'\ndraft = \n+++\n"),
+            "words/a.md:3"
+        );
+        let fenced = "# Hi\n\nText.\n\n```frontmatter\ntitle = 'A'\ndatetime = 'soon'\n```\n";
+        assert_eq!(error_line(fenced), "words/a.md:7");
+    }
+
+    #[test]
+    fn yaml_errors_have_lines() {
+        assert_eq!(error_line("---\ntitle: A\ndraft: 3\n---\n"), "words/a.md:3");
+        assert_eq!(
+            error_line("---\ntitle: A\ndatetime: 2024-13-01\n---\n"),
+            "words/a.md:3"
+        );
+        assert_eq!(error_line("---\n- a\n---\n"), "words/a.md:2");
+        // Running out of frontmatter is reported where it ends
+        assert_eq!(error_line("---\ntitle: A\ntags: [a\n---\n"), "words/a.md:4");
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(
+            error_line("+++\ntitle = 'A'\ndrafts = true\n+++\n"),
+            "words/a.md:3"
+        );
+        assert_eq!(
+            error_line("---\ntitle: A\ndate: 2024-05-01\n---\n"),
+            "words/a.md:3"
+        );
+        let frontmatter = find_frontmatter("---\ndrafts: true\n---\n").unwrap();
+        let error = frontmatter.parse(Path::new("words/a.md")).unwrap_err();
+        assert!(error.to_string().contains("unknown field `drafts`"));
+    }
+
+    #[test]
+    fn error_messages() {
+        let frontmatter = find_frontmatter("---\ntitle: A\ndraft: 3\n---\n").unwrap();
+        let error = frontmatter.parse(Path::new("words/a.md")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "words/a.md:3: draft: invalid type: integer `3`, expected a boolean"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Headers that tell browsers to lock pages down, sent with every response, except for
+//! `Strict-Transport-Security`, which only means anything over HTTPS.
+use serde::Deserialize;
+use tiny_http::Header;
+
+use crate::http::header;
+
+/// How long browsers should only use HTTPS if the site serves it and nothing else is
+/// configured, which is a year.
+const DEFAULT_HSTS_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// Each header can be left out by setting it to an empty string.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Stylesheets and images may come from the site itself, plus cover art from
+    /// elsewhere. Pages and syntect's highlighting use `style` attributes, so those are
+    /// allowed, but not inline `<style>` elements.
+    pub content_security_policy: String,
+    pub referrer_policy:         String,
+    pub permissions_policy:      String,
+    /// For browsers that don't understand `frame-ancestors`.
+    pub frame_options:           String,
+    /// How long browsers should only use HTTPS, in seconds. It's only sent with
+    /// responses over HTTPS, including ones a trusted proxy says it made that way.
+    /// Defaults to a year when TLS is configured, and to not sending it otherwise.
+    pub hsts_max_age:            Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            content_security_policy: [
+                "default-src 'self'",
+                "img-src 'self' https:",
+                "style-src 'self'",
+                "style-src-attr 'unsafe-inline'",
+                "script-src 'none'",
+                "object-src 'none'",
+                "base-uri 'none'",
+                "form-action 'self'",
+                "frame-ancestors 'none'",
+            ]
+            .join("; "),
+            referrer_policy:         "strict-origin-when-cross-origin".to_string(),
+            permissions_policy:      "camera=(), geolocation=(), microphone=(), payment=()"
+                .to_string(),
+            frame_options:           "DENY".to_string(),
+            hsts_max_age:            None,
+        }
+    }
+}
+
+impl Config {
+    /// The headers for every response.
+    pub fn headers(&self) -> Vec<Header> {
+        [
+            (
+                "Content-Security-Policy",
+                self.content_security_policy.as_str(),
+            ),
+            ("X-Content-Type-Options", "nosniff"),
+            ("Referrer-Policy", &self.referrer_policy),
+            ("Permissions-Policy", &self.permissions_policy),
+            ("X-Frame-Options", &self.frame_options),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(field, value)| header(field, value))
+        .collect()
+    }
+
+    /// The `Strict-Transport-Security` header for responses over HTTPS, if there is one.
+    /// `tls` is whether the site serves HTTPS itself.
+    pub fn hsts(&self, tls: bool) -> Option<Header> {
+        let max_age = self
+            .hsts_max_age
+            .or_else(|| tls.then_some(DEFAULT_HSTS_MAX_AGE))?;
+        Some(header(
+            "Strict-Transport-Security",
+            &format!("max-age={max_age}; includeSubDomains"),
+        ))
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::{Condvar, Mutex, PoisonError};
+
+use log::{info, warn};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+static REQUESTED: Mutex<bool> = Mutex::new(false);
+static CHANGED: Condvar = Condvar::new();
+
+/// Ask everything that's running to wrap up.
+pub fn request() {
+    *REQUESTED.lock().unwrap_or_else(PoisonError::into_inner) = true;
+    CHANGED.notify_all();
+}
+
+/// Whether anything has asked to shut down, so long-running work can stop early.
+pub fn requested() -> bool {
+    *REQUESTED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Block until something asks to shut down.
+pub fn wait() {
+    let requested = REQUESTED.lock().unwrap_or_else(PoisonError::into_inner);
+    let _requested = CHANGED
+        .wait_while(requested, |requested| !*requested)
+        .unwrap_or_else(PoisonError::into_inner);
+}
+
+/// Shut down gracefully on SIGTERM or SIGINT. A second one means someone's tired of
+/// waiting, so that exits straight away.
+pub fn listen() -> eyre::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            if requested() {
+                warn!("Received a second signal; exiting without finishing up");
+                std::process::exit(128 + signal);
+            }
+            info!("Received signal {signal}; shutting down");
+            request();
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Serving HTTPS without a proxy in front.
+//!
+//! tiny_http can't change the certificate of a server that's running, so reloading it
+//! starts a new server. The old one stops accepting connections before the new one
+//! starts, so only one of them is ever taking connections from the socket, but it's
+//! kept rather than dropped. Dropping it would leave requests on the connections it
+//! already has waiting in a queue nobody reads, and keep-alive clients would hang on
+//! them. tiny_http can't say when those connections are closed, so old servers are
+//! kept until the process exits, which costs little once their clients are gone.
+//!
+//! tiny_http only stops accepting when its server is dropped, so the old server's
+//! accept thread is stopped by replacing the socket it accepts on with one it can't
+//! accept on. tiny_http logs an error when that happens.
+use std::{
+    ffi::c_int,
+    fs, io,
+    net::{Shutdown, TcpStream},
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixDatagram,
+    },
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, PoisonError, RwLock,
+        atomic::{AtomicBool, Ordering::Relaxed},
+    },
+    time::Duration,
+};
+
+use eyre::Context;
+use log::{error, info, warn};
+use signal_hook::consts::SIGHUP;
+use tiny_http::{Listener, Request, SslConfig};
+
+use crate::bind::{Bind, Socket};
+
+/// How long to wait for requests on the current server at a time while there are old
+/// ones to check as well.
+const RETIRED_POLL: Duration = Duration::from_millis(20);
+
+unsafe extern "C" {
+    /// From the C library, since std doesn't have a way to replace a file descriptor.
+    fn dup2(old: c_int, new: c_int) -> c_int;
+}
+
+/// Where to find the certificate chain and its private key, both PEM encoded.
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub cert: PathBuf,
+    pub key:  PathBuf,
+}
+
+impl Certificate {
+    fn load(&self) -> eyre::Result<SslConfig> {
+        let read = |path: &PathBuf| fs::read(path).context(format!("Failed to read {path:?}"));
+        Ok(SslConfig {
+            certificate: read(&self.cert)?,
+            private_key: read(&self.key)?,
+        })
+    }
+}
+
+/// A tiny_http server, and the file descriptor its accept thread accepts on.
+struct Running {
+    server: tiny_http::Server,
+    fd:     RawFd,
+}
+
+impl Running {
+    fn start(listener: &Socket, ssl: Option<SslConfig>) -> eyre::Result<Self> {
+        let listener = listener.try_clone()?;
+        let fd = match &listener {
+            Listener::Tcp(listener) => listener.as_raw_fd(),
+            Listener::Unix(listener) => listener.as_raw_fd(),
+        };
+        let server = tiny_http::Server::from_listener(listener, ssl).map_err(|e| eyre::eyre!(e))?;
+        Ok(Self { server, fd })
+    }
+}
+
+/// A server on a socket, using TLS if it has a certificate.
+pub struct Server {
+    listener:    Socket,
+    certificate: Option<Certificate>,
+    /// Only missing if reloading failed so badly that the server has to stop.
+    current:     RwLock<Option<Running>>,
+    /// What `current` was started with, to go back to if a new certificate is rejected.
+    ssl:         Mutex<Option<SslConfig>>,
+    /// Servers that were replaced when the certificate was, which no longer accept
+    /// connections but still receive requests on the ones they had.
+    retired:     Mutex<Vec<tiny_http::Server>>,
+    /// Set when SIGHUP asks for the certificate to be reloaded.
+    reload:      Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn new(listener: Socket, certificate: Option<Certificate>) -> eyre::Result<Self> {
+        let ssl = certificate.as_ref().map(Certificate::load).transpose()?;
+        let current = Running::start(&listener, ssl.clone())?;
+        let reload = Arc::new(AtomicBool::new(false));
+        // Without a certificate there's nothing to reload, so SIGHUP is left alone
+        if certificate.is_some() {
+            signal_hook::flag::register(SIGHUP, Arc::clone(&reload))?;
+        }
+        Ok(Self {
+            listener,
+            certificate,
+            current: RwLock::new(Some(current)),
+            ssl: Mutex::new(ssl),
+            retired: Mutex::default(),
+            reload,
+        })
+    }
+
+    pub fn bind(addr: &str, certificate: Option<Certificate>) -> eyre::Result<Self> {
+        Self::new(Bind::Address(addr.to_string()).listen()?, certificate)
+    }
+
+    pub fn is_secure(&self) -> bool {
+        self.certificate.is_some()
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.listener.port()
+    }
+
+    /// A request from one of the retired servers, if any of them has one. Their errors
+    /// are left out, since the first is always from their accept thread stopping.
+    fn pop_retired(&self) -> Option<Request> {
+        let retired = self.retired.lock().unwrap_or_else(PoisonError::into_inner);
+        retired
+            .iter()
+            .find_map(|server| server.try_recv().ok().flatten())
+    }
+
+    fn with_current<T>(
+        &self,
+        f: impl FnOnce(&tiny_http::Server) -> io::Result<T>,
+    ) -> io::Result<T> {
+        match &*self.current.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(running) => f(&running.server),
+            None => Err(io::Error::other(
+                "The server stopped after failing to reload",
+            )),
+        }
+    }
+
+    /// Like [`tiny_http::Server::recv_timeout`], but requests on connections from
+    /// before a reload are handled too. While there are any such connections it might
+    /// return before `timeout` without a request.
+    pub fn recv_timeout(&self, timeout: Duration) -> io::Result<Option<Request>> {
+        if let Some(request) = self.pop_retired() {
+            return Ok(Some(request));
+        }
+        let retired = !self
+            .retired
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty();
+        let timeout = if retired {
+            timeout.min(RETIRED_POLL)
+        } else {
+            timeout
+        };
+        self.with_current(|server| server.recv_timeout(timeout))
+    }
+
+    /// Like [`tiny_http::Server::try_recv`], but requests on connections from before a
+    /// reload are handled too.
+    pub fn try_recv(&self) -> io::Result<Option<Request>> {
+        if let Some(request) = self.pop_retired() {
+            return Ok(Some(request));
+        }
+        self.with_current(|server| server.try_recv())
+    }
+
+    /// Make a server's accept thread stop, without dropping the server.
+    fn stop_accepting(&self, running: &Running) -> io::Result<()> {
+        // Accepting on a datagram socket fails, which stops the accept thread
+        let unusable = UnixDatagram::unbound()?;
+        // SAFETY: the accept thread only accepts on `fd` and closes it when it stops,
+        // and `fd` stays open through this, just as a different socket
+        if unsafe { dup2(unusable.as_raw_fd(), running.fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // The thread is still waiting on the socket it had, so it needs a connection to
+        // wake up. If a client gets there first it's served by the old server instead.
+        if let Socket::Tcp(listener) = &self.listener {
+            let wake = listener.local_addr().and_then(TcpStream::connect);
+            if let Err(e) = wake.and_then(|stream| stream.shutdown(Shutdown::Both)) {
+                warn!("The old server will accept one more connection: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Start using the certificate on disk if SIGHUP asked for it. The old one stays
+    /// in use if the new one can't be loaded, and the server only fails if it can't be
+    /// restarted with either of them.
+    pub fn reload_if_requested(&self) -> eyre::Result<()> {
+        let Some(certificate) = &self.certificate else {
+            return Ok(());
+        };
+        if !self.reload.swap(false, Relaxed) {
+            return Ok(());
+        }
+        let ssl = match certificate.load() {
+            Ok(ssl) => ssl,
+            Err(e) => {
+                error!("Failed to reload the certificate; keeping the old one: {e:#}");
+                return Ok(());
+            }
+        };
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        // The old server has to stop accepting before the new one starts, so there's
+        // never a moment when both are taking connections from the socket
+        if let Some(old) = current.take() {
+            match self.stop_accepting(&old) {
+                Ok(()) => self
+                    .retired
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(old.server),
+                // Dropping it is the only other way to stop it, though anything still
+                // arriving on its connections is lost then
+                Err(e) => error!("Failed to stop the old server accepting connections: {e}"),
+            }
+        }
+        let start = |ssl| Running::start(&self.listener, Some(ssl));
+        let mut last = self.ssl.lock().unwrap_or_else(PoisonError::into_inner);
+        let server = match start(ssl.clone()) {
+            Ok(server) => {
+                info!("Reloaded the certificate from {:?}", certificate.cert);
+                *last = Some(ssl);
+                server
+            }
+            Err(e) => {
+                error!("Failed to reload the certificate; keeping the old one: {e:#}");
+                let ssl = last
+                    .clone()
+                    .ok_or_else(|| eyre::eyre!("No certificate to go back to"))?;
+                start(ssl)?
+            }
+        };
+        *current = Some(server);
+        Ok(())
+    }
+}